@@ -2,7 +2,7 @@
 use serde::{Deserialize, Serialize};
 
 /// Interference flag corresponding to the scan signal.
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum InterferenceFlag {
     /// The signal has the interference of specular reflection
@@ -12,3 +12,22 @@ pub enum InterferenceFlag {
     /// Interference was not observed
     Nothing,
 }
+
+/// Number of samples of a lap with each interference flag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InterferenceCounts {
+    pub specular_reflection: u32,
+    pub ambient_light: u32,
+}
+
+impl InterferenceCounts {
+    /// Counts one more sample with the given flag.
+    pub fn add(&mut self, flag: InterferenceFlag) {
+        match flag {
+            InterferenceFlag::SpecularReflection => self.specular_reflection += 1,
+            InterferenceFlag::AmbientLight => self.ambient_light += 1,
+            InterferenceFlag::Nothing => {}
+        }
+    }
+}
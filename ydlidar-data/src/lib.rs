@@ -4,6 +4,6 @@ pub mod scan;
 pub mod ydlidar_models;
 
 pub use device_info::DeviceInfo;
-pub use flags::InterferenceFlag;
+pub use flags::{InterferenceCounts, InterferenceFlag};
 pub use scan::Scan;
-pub use ydlidar_models::{model_baud_rate, YdlidarModel};
+pub use ydlidar_models::{model_baud_rate, model_max_distance, model_sample_rate, YdlidarModel};
@@ -1,5 +1,7 @@
+use crate::flags::{InterferenceCounts, InterferenceFlag};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::time::{Instant, SystemTime};
 
 /// Struct to hold one lap of lidar scan data.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -9,6 +11,50 @@ pub struct Scan {
     pub angles_radian: Vec<f64>,
     /// Distance to an object (in mm, rounded down).
     pub distances: Vec<u16>,
+    /// Intensity of the returned signal of each point, for the models that report it,
+    /// such as the T-mini Pro. `None` for the other models.
+    pub intensities: Option<Vec<u8>>,
+    /// Interference flag of each point, for the models that report it, such as the
+    /// T-mini Pro. `None` for the other models.
+    pub interference_flags: Option<Vec<InterferenceFlag>>,
+    /// Number of samples of the lap with each interference flag, including the samples
+    /// dropped by the distance limits. Ambient light on most samples hints at sun glare.
+    pub interference_counts: InterferenceCounts,
     /// Checksum validation result of the scan signal.
     pub checksum_correct: bool,
+    /// Checksum validation result of the packet of each point. Only filled when the
+    /// driver marks the points of failing packets, empty otherwise.
+    pub point_checksum_correct: Vec<bool>,
+    /// Number of packets of the lap that passed the checksum.
+    pub packets_accepted: u32,
+    /// Number of packets of the lap that failed the checksum. Depending on the checksum
+    /// policy of the driver, their points were dropped or kept.
+    pub packets_rejected: u32,
+    /// Number of the lap, incremented for each lap the driver produces. A gap tells
+    /// that laps were dropped on the way.
+    pub sequence: u64,
+    /// Host monotonic time at which the first packet of the lap was received.
+    /// `None` if the reception time is unknown. It is not serialized, as it is only
+    /// meaningful in the process that received the lap.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub first_packet_instant: Option<Instant>,
+    /// Host monotonic time at which the last packet of the lap was received.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub last_packet_instant: Option<Instant>,
+    /// Host system time at which the first packet of the lap was received.
+    pub first_packet_time: Option<SystemTime>,
+    /// Host system time at which the last packet of the lap was received.
+    pub last_packet_time: Option<SystemTime>,
+    /// Rotation frequency in Hz, as sent by the device in the lap start packet, or else
+    /// measured from the time between two lap starts. `None` if neither is known.
+    pub scan_frequency: Option<f64>,
+    /// Number of bytes discarded by the driver while receiving the lap.
+    pub discarded_bytes: u64,
+}
+
+impl Scan {
+    /// Number of packets of the lap, whether they passed the checksum or not.
+    pub fn n_packets(&self) -> u32 {
+        self.packets_accepted + self.packets_rejected
+    }
 }
@@ -1,5 +1,6 @@
 #[non_exhaustive]
 #[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum YdlidarModel {
     TMiniPro = 150,
     X2 = 210,
@@ -47,6 +48,22 @@ pub fn model_baud_rate(model: YdlidarModel) -> u32 {
     }
 }
 
+/// Number of samples the model measures per second.
+pub fn model_sample_rate(model: YdlidarModel) -> u32 {
+    match model {
+        YdlidarModel::TMiniPro => 4000,
+        YdlidarModel::X2 => 3000,
+    }
+}
+
+/// Maximum rated distance of the model, in mm.
+pub fn model_max_distance(model: YdlidarModel) -> u16 {
+    match model {
+        YdlidarModel::TMiniPro => 12000,
+        YdlidarModel::X2 => 8000,
+    }
+}
+
 impl TryFrom<u8> for YdlidarModel {
     type Error = ();
 
@@ -0,0 +1,124 @@
+//! Counts the heap allocations made by the driver for each lap it decodes.
+//!
+//! Run with `cargo bench --bench allocations`.
+
+use serialport::{SerialPort, TTYPort};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use ydlidar_data::YdlidarModel;
+use ydlidar_driver::{DriverBuilder, DriverEvent};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const WARMUP_LAPS: usize = 100;
+const MEASURED_LAPS: usize = 1000;
+const PACKETS_PER_LAP: usize = 12;
+
+/// One T-mini Pro lap made of a lap start packet followed by packets of 40 samples.
+fn lap() -> Vec<u8> {
+    let start_packet = [
+        0xAA, 0x55, 0xC7, 0x01, 0x01, 0x15, 0x01, 0x15, 0x1B, 0x56, 0x14, 0x62, 0x02,
+    ];
+    let packet = [
+        0xAA, 0x55, 0x24, 0x28, 0xF5, 0x4C, 0x85, 0x5E, 0x9D, 0x70, 0xCE, 0xE2, 0x07, 0xBC, 0xFA,
+        0x07, 0xCC, 0xB6, 0x07, 0xC8, 0xB6, 0x07, 0xC4, 0xBA, 0x07, 0xCB, 0xCA, 0x07, 0xC8, 0xAE,
+        0x09, 0xC5, 0x9E, 0x09, 0xC7, 0x9E, 0x09, 0xC2, 0x9E, 0x09, 0xC1, 0x92, 0x09, 0xC0, 0x8A,
+        0x09, 0xC1, 0x86, 0x09, 0xBE, 0x86, 0x09, 0xC5, 0x86, 0x09, 0xC3, 0x8A, 0x09, 0xBC, 0x8A,
+        0x09, 0xC6, 0x8A, 0x09, 0xC6, 0x8A, 0x09, 0xC2, 0x8E, 0x09, 0xC5, 0x8E, 0x09, 0xC3, 0x92,
+        0x09, 0xC4, 0xAA, 0x09, 0xC9, 0xB2, 0x09, 0xC9, 0xBA, 0x09, 0xC5, 0xC2, 0x09, 0xC9, 0xCE,
+        0x09, 0xBF, 0xCE, 0x09, 0xBE, 0xCE, 0x09, 0xBA, 0xCE, 0x09, 0xBE, 0xD6, 0x09, 0xBB, 0xD6,
+        0x09, 0xBF, 0xE2, 0x09, 0xBB, 0xF2, 0x09, 0xC1, 0x0A, 0x0A, 0xBF, 0x1A, 0x0A, 0xB9, 0x1E,
+        0x0A, 0xAA, 0x22, 0x0A, 0x9E, 0x2A, 0x0A, 0xCB, 0x7A, 0x15,
+    ];
+    let mut lap = start_packet.to_vec();
+    for _ in 0..PACKETS_PER_LAP {
+        lap.extend_from_slice(&packet);
+    }
+    lap
+}
+
+/// Streams laps through a pseudo terminal and returns the number of allocations per lap.
+fn allocations_per_lap(recycle: bool) -> f64 {
+    let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+    master.set_timeout(Duration::from_millis(10)).unwrap();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer_stop = stop.clone();
+    let writer = std::thread::spawn(move || {
+        // Leaves time for the driver to flush the port before streaming
+        std::thread::sleep(Duration::from_millis(100));
+        let lap = lap();
+        while !writer_stop.load(Ordering::Relaxed) {
+            let _ = master.write_all(&lap);
+        }
+    });
+
+    let (driver_threads, event_rx) =
+        DriverBuilder::new(&slave.name().unwrap(), YdlidarModel::TMiniPro)
+            .read_timeout(10)
+            .build()
+            .unwrap();
+
+    let mut n_laps = 0;
+    let mut start = 0;
+    while n_laps < WARMUP_LAPS + MEASURED_LAPS {
+        if n_laps == WARMUP_LAPS {
+            start = ALLOCATIONS.load(Ordering::Relaxed);
+        }
+        match event_rx.recv().unwrap() {
+            DriverEvent::Scan(scan) => {
+                n_laps += 1;
+                if recycle {
+                    driver_threads.recycle(scan);
+                }
+            }
+            event => panic!("Unexpected event {event:?}"),
+        }
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - start;
+
+    stop.store(true, Ordering::Relaxed);
+    drop(driver_threads);
+    writer.join().unwrap();
+    allocations as f64 / MEASURED_LAPS as f64
+}
+
+fn main() {
+    println!(
+        "{} samples per lap, {} laps measured",
+        1 + PACKETS_PER_LAP * 40,
+        MEASURED_LAPS
+    );
+    println!(
+        "allocations per lap, scans dropped:  {:.2}",
+        allocations_per_lap(false)
+    );
+    println!(
+        "allocations per lap, scans recycled: {:.2}",
+        allocations_per_lap(true)
+    );
+}
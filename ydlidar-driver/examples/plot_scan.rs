@@ -5,10 +5,9 @@ use plotters::prelude::{ChartBuilder, Circle, BLACK, BLUE, CYAN, GREEN, RED, WHI
 use plotters::style::Color;
 use plotters_piston::{draw_piston_window, PistonBackend};
 use std::net::TcpStream;
-use std::sync::mpsc::Receiver;
-use ydlidar_data::{Scan, YdlidarModel};
+use ydlidar_data::YdlidarModel;
 
-use ydlidar_driver::{run_driver, DriverThreads};
+use ydlidar_driver::{run_driver, DriverEvent, DriverThreads, EventReceiver};
 
 fn get_args() -> (Option<String>, Option<String>) {
     let matches = Command::new("LiDAR data receiver.")
@@ -42,7 +41,7 @@ fn main() {
 
     let mut listener: Option<TcpStream> = None;
     let mut driver_threads: Option<DriverThreads> = None;
-    let mut scan_rx: Option<Receiver<Scan>> = None;
+    let mut event_rx: Option<EventReceiver> = None;
 
     match (port_name, ip) {
         (_, Some(ip)) => {
@@ -51,7 +50,7 @@ fn main() {
         (Some(port), _) => {
             let driver = run_driver(&port, YdlidarModel::X2, 200, 10, 0, 100).unwrap();
             driver_threads = Some(driver.0);
-            scan_rx = Some(driver.1);
+            event_rx = Some(driver.1);
         }
         (_, _) => panic!("Either --port or --ip has to be passed!"),
     }
@@ -63,12 +62,21 @@ fn main() {
     window.set_max_fps(FPS);
     let draw = |b: PistonBackend| {
         let scan: Vec<(f64, f64)>;
-        match (&listener, &scan_rx) {
+        match (&listener, &event_rx) {
             (Some(listener), _) => {
                 scan = rmp_serde::from_read(listener).unwrap();
             }
-            (_, Some(scan_rx)) => {
-                let raw_scan = scan_rx.recv().unwrap();
+            (_, Some(event_rx)) => {
+                let raw_scan = loop {
+                    match event_rx.recv().unwrap() {
+                        DriverEvent::Scan(scan) => break scan,
+                        DriverEvent::Error(e) => eprintln!("{e}"),
+                        DriverEvent::Disconnected | DriverEvent::Stopped => {
+                            panic!("The driver stopped!")
+                        }
+                        _ => {}
+                    }
+                };
                 scan = raw_scan
                     .angles_radian
                     .iter()
@@ -81,7 +89,7 @@ fn main() {
                     .collect();
             }
             (None, None) => {
-                panic!("Either a TcpStream or a Receiver<Scan> should be set!");
+                panic!("Either a TcpStream or a Receiver<DriverEvent> should be set!");
             }
         }
 
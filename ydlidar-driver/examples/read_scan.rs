@@ -2,7 +2,7 @@ use clap::{Arg, Command};
 use std::io::Write;
 use std::net::TcpListener;
 use ydlidar_data::YdlidarModel;
-use ydlidar_driver::run_driver;
+use ydlidar_driver::{run_driver, DriverEvent};
 
 fn get_port_name() -> String {
     let matches = Command::new("LiDAR data receiver.")
@@ -26,15 +26,19 @@ fn main() {
     let listener = TcpListener::bind("0.0.0.0:1500").unwrap();
     let (mut socket, _) = listener.accept().unwrap();
 
-    let (driver_threads, scan_rx) =
+    let (driver_threads, event_rx) =
         run_driver(&port_name, YdlidarModel::X2, 200, 10, 0, 100).unwrap();
 
     loop {
-        let scan = scan_rx.recv();
-        if scan.is_err() {
-            break;
-        }
-        let scan = scan.unwrap();
+        let scan = match event_rx.recv() {
+            Ok(DriverEvent::Scan(scan)) => scan,
+            Ok(DriverEvent::Error(e)) => {
+                eprintln!("{e}");
+                continue;
+            }
+            Ok(DriverEvent::Disconnected) | Ok(DriverEvent::Stopped) | Err(_) => break,
+            Ok(_) => continue,
+        };
         let scans: Vec<(f64, f64)> = scan
             .angles_radian
             .iter()
@@ -46,6 +50,7 @@ fn main() {
                 (x, y)
             })
             .collect();
+        driver_threads.recycle(scan);
         let data = rmp_serde::to_vec(&scans).unwrap();
         let res = socket.write_all(&data);
         if res.is_err() {
@@ -0,0 +1,382 @@
+//! Async driver for the tokio runtime, enabled by the `tokio` feature.
+//! It is only available on unix, as it waits on the file descriptor of the serial
+//! port: the feature has no effect on other targets.
+//!
+//! The laps are decoded by the same decoder as the threaded driver and exposed as a
+//! [`Stream`](futures_core::Stream). To query the device first, open an
+//! [`AsyncSerialPort`] and pass it to `DriverBuilder::build_async_with_port`.
+//!
+//! ```no_run
+//! use futures::StreamExt;
+//! use ydlidar_data::YdlidarModel;
+//! use ydlidar_driver::DriverBuilder;
+//!
+//! # async fn run() -> Result<(), ydlidar_driver::YDLidarError> {
+//! let mut scans = DriverBuilder::new("/dev/ttyUSB0", YdlidarModel::X2)
+//!     .build_async()
+//!     .await?;
+//! while let Some(scan) = scans.next().await {
+//!     println!("{} points", scan?.distances.len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::constants::{
+    HEADER_SIZE, LIDAR_ANS_LENGTH_DEVHEALTH, LIDAR_ANS_LENGTH_DEVINFO, LIDAR_ANS_TYPE_DEVHEALTH,
+    LIDAR_ANS_TYPE_DEVINFO, LIDAR_CMD_GET_DEVICE_HEALTH, LIDAR_CMD_GET_DEVICE_INFO,
+    LIDAR_CMD_SYNC_BYTE, N_READ_TRIALS, READ_CHUNK_SIZE,
+};
+use crate::decoder::ScanDecoder;
+use crate::error::YDLidarError;
+use crate::packet::{parse_device_health, parse_device_info, validate_response_header};
+use crate::serial::open_error;
+use futures_core::Stream;
+use serialport::{ClearBuffer, SerialPort, TTYPort};
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use ydlidar_data::{DeviceInfo, Scan};
+
+/// Time to wait for the response to a command, the same as the blocking reads.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(N_READ_TRIALS as u64 * 10);
+
+/// Serial port driven by the tokio reactor.
+pub struct AsyncSerialPort {
+    inner: AsyncFd<TTYPort>,
+}
+
+impl AsyncSerialPort {
+    /// Opens a serial port. Must be called from within a tokio runtime.
+    pub fn open(port_name: &str, baud_rate: u32) -> Result<Self, YDLidarError> {
+        let port = serialport::new(port_name, baud_rate)
+            .open_native()
+            .map_err(|e| open_error(port_name, baud_rate, e))?;
+        Ok(AsyncSerialPort::new(port)?)
+    }
+
+    /// Wraps a port that is already open. Must be called from within a tokio runtime.
+    pub fn new(mut port: TTYPort) -> io::Result<Self> {
+        // The port is only read or written once the reactor reports it ready
+        port.set_timeout(Duration::ZERO)?;
+        Ok(AsyncSerialPort {
+            inner: AsyncFd::new(port)?,
+        })
+    }
+
+    /// Drops the bytes received but not read yet.
+    pub fn clear_input(&self) -> Result<(), YDLidarError> {
+        self.inner.get_ref().clear(ClearBuffer::Input)?;
+        Ok(())
+    }
+
+    /// Drops the pending data before scanning, like the threaded driver does.
+    async fn reset_scan(&self) -> Result<(), YDLidarError> {
+        self.clear_input()?;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        self.clear_input()
+    }
+
+    /// Waits until the device sends its first bytes.
+    async fn wait_for_data(&self, timeout: u64) -> Result<(), YDLidarError> {
+        match tokio::time::timeout(Duration::from_millis(timeout), self.inner.readable()).await {
+            Ok(ready) => ready.map(|_| ()).map_err(YDLidarError::IoError),
+            Err(_) => Err(YDLidarError::NoDataReceived(timeout)),
+        }
+    }
+
+    fn poll_read_bytes(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_read_ready_mut(cx))?;
+            match guard.try_io(|inner| would_block_on_timeout(inner.get_mut().read(buf))) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// A read or write on a port with a zero timeout fails with `TimedOut` when the port
+/// is not ready, which the reactor expects as `WouldBlock`.
+fn would_block_on_timeout(result: io::Result<usize>) -> io::Result<usize> {
+    result.map_err(|e| match e.kind() {
+        io::ErrorKind::TimedOut => io::ErrorKind::WouldBlock.into(),
+        _ => e,
+    })
+}
+
+impl AsyncRead for AsyncSerialPort {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n_read = ready!(self
+            .get_mut()
+            .poll_read_bytes(cx, buf.initialize_unfilled()))?;
+        buf.advance(n_read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for AsyncSerialPort {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = ready!(this.inner.poll_write_ready_mut(cx))?;
+            match guard.try_io(|inner| would_block_on_timeout(inner.get_mut().write(buf))) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Written bytes are handed to the kernel right away
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+async fn send_command(port: &mut AsyncSerialPort, command: u8) -> io::Result<()> {
+    port.write_all(&[LIDAR_CMD_SYNC_BYTE, command]).await
+}
+
+async fn read(port: &mut AsyncSerialPort, data_size: usize) -> Result<Vec<u8>, YDLidarError> {
+    let mut data = vec![0; data_size];
+    match tokio::time::timeout(RESPONSE_TIMEOUT, port.read_exact(&mut data)).await {
+        Ok(result) => {
+            result?;
+            Ok(data)
+        }
+        Err(_) => Err(YDLidarError::TimeoutError()),
+    }
+}
+
+/// Async version of [`check_device_health`](crate::check_device_health).
+pub async fn check_device_health(port: &mut AsyncSerialPort) -> Result<(), YDLidarError> {
+    send_command(port, LIDAR_CMD_GET_DEVICE_HEALTH).await?;
+    let header = read(port, HEADER_SIZE).await?;
+    validate_response_header(
+        &header,
+        Some(LIDAR_ANS_LENGTH_DEVHEALTH),
+        LIDAR_ANS_TYPE_DEVHEALTH,
+    )?;
+    let health = read(port, LIDAR_ANS_LENGTH_DEVHEALTH.into()).await?;
+    parse_device_health(&health)
+}
+
+/// Async version of [`get_device_info`](crate::get_device_info).
+pub async fn get_device_info(port: &mut AsyncSerialPort) -> Result<DeviceInfo, YDLidarError> {
+    send_command(port, LIDAR_CMD_GET_DEVICE_INFO).await?;
+    let header = read(port, HEADER_SIZE).await?;
+    validate_response_header(
+        &header,
+        Some(LIDAR_ANS_LENGTH_DEVINFO),
+        LIDAR_ANS_TYPE_DEVINFO,
+    )?;
+    let info = read(port, LIDAR_ANS_LENGTH_DEVINFO.into()).await?;
+    Ok(parse_device_info(&info))
+}
+
+/// Stream of the laps read from the device.
+/// The stream yields an error and ends when the device stops responding.
+pub struct ScanStream {
+    port: AsyncSerialPort,
+    decoder: ScanDecoder,
+    chunk: [u8; READ_CHUNK_SIZE],
+    finished: bool,
+}
+
+impl ScanStream {
+    /// Prepares the port and waits for the device to stream.
+    pub(crate) async fn start(
+        port: AsyncSerialPort,
+        decoder: ScanDecoder,
+        data_timeout: u64,
+    ) -> Result<Self, YDLidarError> {
+        port.reset_scan().await?;
+        port.wait_for_data(data_timeout).await?;
+        Ok(ScanStream {
+            port,
+            decoder,
+            chunk: [0; READ_CHUNK_SIZE],
+            finished: false,
+        })
+    }
+
+    /// Hands a scan back so that its storage is reused for a later lap
+    /// instead of being allocated again.
+    pub fn recycle(&mut self, scan: Scan) {
+        if self.decoder.needs_scan() {
+            self.decoder.recycle(scan);
+        }
+    }
+}
+
+impl Stream for ScanStream {
+    type Item = Result<Scan, YDLidarError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(scan) = this.decoder.next_scan() {
+                return Poll::Ready(Some(Ok(scan)));
+            }
+            if this.finished {
+                return Poll::Ready(None);
+            }
+            match ready!(this.port.poll_read_bytes(cx, &mut this.chunk)) {
+                Ok(n_read) if n_read > 0 => {
+                    let (instant, system_time) = (Instant::now(), SystemTime::now());
+                    this.decoder
+                        .push_at(&this.chunk[..n_read], instant, system_time);
+                }
+                result => {
+                    // The device is gone
+                    this.finished = true;
+                    let e = result.err().unwrap_or(io::ErrorKind::UnexpectedEof.into());
+                    return Poll::Ready(Some(Err(YDLidarError::IoError(e))));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DriverBuilder;
+    use crate::test_util::{LAP_START, SCAN_RESPONSE};
+    use futures::StreamExt;
+    use ydlidar_data::YdlidarModel;
+
+    /// Writes to the device once the stream dropped the data pending when it started.
+    async fn write_later(master: &mut TTYPort, bytes: &[u8]) {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        master.write_all(bytes).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_device_health() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let mut port = AsyncSerialPort::new(slave).unwrap();
+
+        master
+            .write_all(&[0xA5, 0x5A, 0x03, 0x00, 0x00, 0x00, 0x06, 0x02, 0x00, 0x00])
+            .unwrap();
+        assert!(matches!(
+            check_device_health(&mut port).await,
+            Err(YDLidarError::DeviceHealthError(0x02))
+        ));
+
+        let mut command = [0u8; 2];
+        master.read_exact(&mut command).unwrap();
+        assert_eq!(command, [0xA5, 0x92]);
+
+        // Nothing is sent back
+        assert!(matches!(
+            check_device_health(&mut port).await,
+            Err(YDLidarError::TimeoutError())
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_device_info() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let mut port = AsyncSerialPort::new(slave).unwrap();
+
+        master
+            .write_all(&[
+                0xA5, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x04, 0x96, 0x00, 0x01, 0x02, 0x02, 0x00, 0x02,
+                0x02, 0x01, 0x01, 0x00, 0x03, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+            ])
+            .unwrap();
+        let info = get_device_info(&mut port).await.unwrap();
+        assert_eq!(info.model_number, 150);
+        assert_eq!(info.firmware_major_version, 1);
+        assert_eq!(info.hardware_version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let builder = DriverBuilder::new(&slave.name().unwrap(), YdlidarModel::TMiniPro);
+        let (scans, _) = tokio::join!(
+            builder.build_async(),
+            write_later(&mut master, &SCAN_RESPONSE)
+        );
+        let mut scans = scans.unwrap();
+        master.write_all(&LAP_START).unwrap();
+        master.write_all(&LAP_START).unwrap();
+
+        // The lap before the first lap start
+        let scan = scans.next().await.unwrap().unwrap();
+        assert!(scan.distances.is_empty());
+        let scan = scans.next().await.unwrap().unwrap();
+        assert_eq!(scan.distances.len(), 1);
+        assert!(scan.checksum_correct);
+
+        drop(master);
+        assert!(matches!(
+            scans.next().await,
+            Some(Err(YDLidarError::IoError(_)))
+        ));
+        assert!(scans.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_build_async_with_port() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let builder = DriverBuilder::new(&slave.name().unwrap(), YdlidarModel::TMiniPro);
+        let mut port = AsyncSerialPort::new(slave).unwrap();
+
+        master
+            .write_all(&[0xA5, 0x5A, 0x03, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00])
+            .unwrap();
+        check_device_health(&mut port).await.unwrap();
+
+        let (scans, _) = tokio::join!(
+            builder.build_async_with_port(port),
+            write_later(&mut master, &SCAN_RESPONSE)
+        );
+        let mut scans = scans.unwrap();
+        master.write_all(&LAP_START).unwrap();
+        master.write_all(&LAP_START).unwrap();
+        scans.next().await.unwrap().unwrap();
+        assert_eq!(scans.next().await.unwrap().unwrap().distances.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream_timestamps() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let builder = DriverBuilder::new(&slave.name().unwrap(), YdlidarModel::TMiniPro);
+        let (scans, _) = tokio::join!(
+            builder.build_async(),
+            write_later(&mut master, &SCAN_RESPONSE)
+        );
+        let mut scans = scans.unwrap();
+        let before = Instant::now();
+        master.write_all(&LAP_START).unwrap();
+        master.write_all(&LAP_START).unwrap();
+
+        scans.next().await.unwrap().unwrap();
+        let scan = scans.next().await.unwrap().unwrap();
+        let first_instant = scan.first_packet_instant.unwrap();
+        let last_instant = scan.last_packet_instant.unwrap();
+        assert!(before <= first_instant);
+        assert!(first_instant <= last_instant);
+        assert!(last_instant <= Instant::now());
+        assert!(scan.first_packet_time.unwrap() <= scan.last_packet_time.unwrap());
+    }
+}
@@ -0,0 +1,98 @@
+//! Acts as a YDLidar on a pseudo terminal and prints its path.
+//!
+//! Usage: `ydlidar-emulator [--model x2|tminipro] [--capture FILE] [--distance MM] [--frequency HZ]`
+//!
+//! Without a capture file, the emulator streams laps of a round room.
+
+#[cfg(unix)]
+fn main() {
+    use ydlidar_data::YdlidarModel;
+    use ydlidar_driver::{CaptureReader, Emulator, EmulatorSource};
+
+    let mut model = None;
+    let mut capture = None;
+    let mut distance: u16 = 1000;
+    let mut frequency: f64 = 7.;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().unwrap_or_else(|| {
+                eprintln!("Missing value for {arg}");
+                std::process::exit(2);
+            })
+        };
+        match arg.as_str() {
+            "--model" => {
+                model = match value().to_lowercase().as_str() {
+                    "x2" => Some(YdlidarModel::X2),
+                    "tminipro" => Some(YdlidarModel::TMiniPro),
+                    other => {
+                        eprintln!("Unknown model {other}");
+                        std::process::exit(2);
+                    }
+                }
+            }
+            "--capture" => capture = Some(std::path::PathBuf::from(value())),
+            "--distance" => {
+                let value = value();
+                distance = value.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid distance {value}");
+                    std::process::exit(2);
+                })
+            }
+            "--frequency" => {
+                let value = value();
+                frequency = match value.parse::<f64>() {
+                    Ok(frequency) if frequency.is_finite() && frequency > 0. => frequency,
+                    _ => {
+                        eprintln!("Invalid frequency {value}");
+                        std::process::exit(2);
+                    }
+                }
+            }
+            other => {
+                eprintln!("Unknown argument {other}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let (model, source) = match capture {
+        Some(path) => {
+            // Defaults to the model the capture was recorded from
+            let header = match CaptureReader::open(&path) {
+                Ok(reader) => reader.header().clone(),
+                Err(e) => {
+                    eprintln!("Cannot read the capture {}: {e}", path.display());
+                    std::process::exit(2);
+                }
+            };
+            (model.unwrap_or(header.model), EmulatorSource::Capture(path))
+        }
+        None => {
+            let model = model.unwrap_or(YdlidarModel::X2);
+            let source =
+                EmulatorSource::round_room(model, distance, frequency).unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    std::process::exit(2);
+                });
+            (model, source)
+        }
+    };
+
+    let emulator = Emulator::start(model, source).unwrap_or_else(|e| {
+        eprintln!("Cannot start the emulator: {e}");
+        std::process::exit(1);
+    });
+    println!("{}", emulator.path());
+    loop {
+        std::thread::park();
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("The emulator relies on pseudo terminals, which are only available on unix");
+    std::process::exit(1);
+}
@@ -0,0 +1,531 @@
+use crate::capture::{CaptureHeader, CaptureWriter};
+use crate::constants::READ_CHUNK_SIZE;
+use crate::decoder::{ChecksumPolicy, ScanDecoder};
+use crate::driver_threads::{
+    parse_packets, read_device_signal, DriverThreads, ParserConfig, ReaderConfig, ReaderEvent,
+};
+use crate::error::YDLidarError;
+use crate::output::{BackpressurePolicy, Broadcaster, EventReceiver, EventSender, Subscribers};
+use crate::reconnect::{ReconnectPolicy, Reconnector};
+use crate::serial::{reset_scan, wait_for_data};
+use crate::transport::{IntoTransport, Transport};
+use crate::watchdog::Watchdog;
+use crossbeam_channel::bounded;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use ydlidar_data::{model_baud_rate, model_max_distance, DeviceInfo, Scan, YdlidarModel};
+
+/// Builder to configure and launch the YDLiDAR driver.
+///
+/// Every field has a default drawn from the model, so only the values that
+/// differ from the defaults need to be set.
+///
+/// ```no_run
+/// use ydlidar_data::YdlidarModel;
+/// use ydlidar_driver::DriverBuilder;
+///
+/// let (driver_threads, event_rx) = DriverBuilder::new("/dev/ttyUSB0", YdlidarModel::X2)
+///     .max_distance(5000)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct DriverBuilder {
+    port_name: String,
+    model: YdlidarModel,
+    baud_rate: u32,
+    min_distance: u16,
+    max_distance: u16,
+    scan_buffer: usize,
+    out_buffer: usize,
+    backpressure: BackpressurePolicy,
+    send_after: usize,
+    checksum_policy: ChecksumPolicy,
+    read_timeout: u64,
+    data_timeout: u64,
+    reconnect: Option<ReconnectPolicy>,
+    watchdog: Option<Watchdog>,
+    capture: Option<PathBuf>,
+    device_info: Option<DeviceInfo>,
+}
+
+impl DriverBuilder {
+    /// Creates a builder with the defaults of the given model.
+    /// # Arguments
+    ///
+    /// * `port_name` - Serial port name such as `/dev/ttyUSB0`, `tcp://host:port` or `rfc2217://host:port`.
+    /// * `model` - Model
+    pub fn new(port_name: &str, model: YdlidarModel) -> Self {
+        DriverBuilder {
+            port_name: port_name.to_string(),
+            model,
+            baud_rate: model_baud_rate(model),
+            min_distance: 1,
+            max_distance: model_max_distance(model),
+            scan_buffer: 200,
+            out_buffer: 10,
+            backpressure: BackpressurePolicy::Block,
+            send_after: 0,
+            checksum_policy: ChecksumPolicy::Keep,
+            read_timeout: 100,
+            data_timeout: 1000,
+            reconnect: None,
+            watchdog: None,
+            capture: None,
+            device_info: None,
+        }
+    }
+
+    /// Baud rate of the serial port. Defaults to the baud rate of the model.
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    /// Minimum distance to keep points (inclusive, e.g. 1 -> distances of 0 will be discarded)
+    pub fn min_distance(mut self, min_distance: u16) -> Self {
+        self.min_distance = min_distance;
+        self
+    }
+
+    /// Maximum distance to keep points (inclusive, e.g. 5000 -> distances bigger than 5000 will be discarded)
+    pub fn max_distance(mut self, max_distance: u16) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    /// Number of raw serial reads buffered between the reader and the parser threads.
+    /// The read chunks are allocated once when the driver is launched.
+    pub fn scan_buffer(mut self, scan_buffer: usize) -> Self {
+        self.scan_buffer = scan_buffer;
+        self
+    }
+
+    /// Number of events buffered in the output channel.
+    pub fn out_buffer(mut self, out_buffer: usize) -> Self {
+        self.out_buffer = out_buffer;
+        self
+    }
+
+    /// What to do when the consumer does not keep up with the output channel.
+    /// Defaults to `BackpressurePolicy::Block`.
+    pub fn backpressure(mut self, policy: BackpressurePolicy) -> Self {
+        self.backpressure = policy;
+        self
+    }
+
+    /// Sends a scan once it holds this many points, even if the lap is not complete.
+    /// 0 sends scans at the beginning of each lap only.
+    pub fn send_after(mut self, send_after: usize) -> Self {
+        self.send_after = send_after;
+        self
+    }
+
+    /// What to do with the points of packets that fail the checksum.
+    /// Defaults to `ChecksumPolicy::Keep`.
+    pub fn checksum_policy(mut self, policy: ChecksumPolicy) -> Self {
+        self.checksum_policy = policy;
+        self
+    }
+
+    /// Time in milliseconds a read blocks while the device sends nothing.
+    /// The reader thread notices a termination request within this time.
+    pub fn read_timeout(mut self, read_timeout: u64) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Time in milliseconds to wait for the first bytes from the device after opening it.
+    pub fn data_timeout(mut self, data_timeout: u64) -> Self {
+        self.data_timeout = data_timeout;
+        self
+    }
+
+    /// Reopens the transport with this policy when the device disappears.
+    /// By default the driver stops with `DriverEvent::Disconnected`.
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// Reports `DriverEvent::Stalled` when the lidar stops streaming.
+    pub fn watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Records every byte read from the device into a capture file, which is replaced
+    /// if it exists. See `CaptureWriter` for the format.
+    pub fn capture(mut self, path: impl Into<PathBuf>) -> Self {
+        self.capture = Some(path.into());
+        self
+    }
+
+    /// Device information written into the header of the capture file, e.g. from
+    /// `get_device_info` for models that answer commands.
+    pub fn device_info(mut self, device_info: DeviceInfo) -> Self {
+        self.device_info = Some(device_info);
+        self
+    }
+
+    /// Checks that the configuration can be used to launch the driver.
+    pub fn validate(&self) -> Result<(), YDLidarError> {
+        if self.baud_rate == 0 {
+            return Err(YDLidarError::InvalidBaudRate(self.baud_rate));
+        }
+        if self.min_distance > self.max_distance {
+            return Err(YDLidarError::InvalidConfiguration(format!(
+                "min_distance ({}) is greater than max_distance ({})",
+                self.min_distance, self.max_distance
+            )));
+        }
+        if self.scan_buffer == 0 {
+            return Err(YDLidarError::InvalidConfiguration(
+                "scan_buffer must be greater than 0".to_string(),
+            ));
+        }
+        if self.out_buffer == 0 {
+            return Err(YDLidarError::InvalidConfiguration(
+                "out_buffer must be greater than 0".to_string(),
+            ));
+        }
+        if let Some(policy) = &self.reconnect {
+            // The backoff doubles from the initial one, so a zero backoff never grows
+            if policy.initial_backoff == 0 {
+                return Err(YDLidarError::InvalidConfiguration(
+                    "initial_backoff must be greater than 0".to_string(),
+                ));
+            }
+            if policy.initial_backoff > policy.max_backoff {
+                return Err(YDLidarError::InvalidConfiguration(format!(
+                    "initial_backoff ({}) is greater than max_backoff ({})",
+                    policy.initial_backoff, policy.max_backoff
+                )));
+            }
+        }
+        if let Some(watchdog) = &self.watchdog {
+            if watchdog.packet_timeout == Some(0) || watchdog.lap_timeout == Some(0) {
+                return Err(YDLidarError::InvalidConfiguration(
+                    "watchdog timeouts must be greater than 0".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn decoder(&self) -> ScanDecoder {
+        ScanDecoder::new(self.model)
+            .min_distance(self.min_distance)
+            .max_distance(self.max_distance)
+            .send_after(self.send_after)
+            .checksum_policy(self.checksum_policy)
+    }
+
+    /// Opens the serial port or connects to the network endpoint, and launches the
+    /// driver threads.
+    ///
+    /// Fails if the port cannot be opened or if the device does not send any data
+    /// within the `data_timeout`.
+    pub fn build(self) -> Result<(DriverThreads, EventReceiver), YDLidarError> {
+        self.validate()?;
+
+        let port = self.port_name.as_str().into_transport(self.baud_rate)?;
+        self.launch(port)
+    }
+
+    /// Launches the driver threads over the given transport instead of opening
+    /// `port_name`. The baud rate is applied to the transport, and reconnecting
+    /// relies on `Transport::reconnect`.
+    ///
+    /// Fails if the device does not send any data within the `data_timeout`.
+    pub fn build_with_transport(
+        self,
+        mut transport: Box<dyn Transport>,
+    ) -> Result<(DriverThreads, EventReceiver), YDLidarError> {
+        self.validate()?;
+
+        transport.set_baud_rate(self.baud_rate)?;
+        self.launch(transport)
+    }
+
+    fn launch(
+        self,
+        mut port: Box<dyn Transport>,
+    ) -> Result<(DriverThreads, EventReceiver), YDLidarError> {
+        reset_scan(port.as_mut())?;
+        wait_for_data(port.as_mut(), self.data_timeout)?;
+
+        // The X2 lidar does not support commands
+        // check_device_health(&mut port)?;
+        // get_device_info(&mut port)?;
+
+        let (reader_terminator_tx, reader_terminator_rx) = bounded(1);
+        let (parser_terminator_tx, parser_terminator_rx) = bounded(1);
+        let (restart_tx, restart_rx) = bounded(1);
+        let (scan_data_tx, scan_data_rx) = bounded::<ReaderEvent>(self.scan_buffer);
+        let (chunk_pool_tx, chunk_pool_rx) = bounded::<Vec<u8>>(self.scan_buffer);
+        for _ in 0..self.scan_buffer {
+            let _ = chunk_pool_tx.send(Vec::with_capacity(READ_CHUNK_SIZE));
+        }
+        let (scan_pool_tx, scan_pool_rx) = bounded::<Scan>(self.out_buffer + 1);
+
+        let capture = match &self.capture {
+            Some(path) => {
+                let header = CaptureHeader {
+                    model: self.model,
+                    baud_rate: self.baud_rate,
+                    device_info: self.device_info.clone(),
+                };
+                Some(CaptureWriter::create(path, &header)?)
+            }
+            None => None,
+        };
+        let reader_config = ReaderConfig {
+            read_timeout: self.read_timeout,
+            reconnector: self.reconnect.clone().map(|policy| Reconnector { policy }),
+            capture,
+        };
+        let reader_thread = Some(std::thread::spawn(move || {
+            read_device_signal(
+                port.as_mut(),
+                scan_data_tx,
+                chunk_pool_rx,
+                reader_terminator_rx,
+                restart_rx,
+                reader_config,
+            );
+        }));
+
+        let dropped_laps = Arc::new(AtomicU64::new(0));
+        let (event_tx, event_rx, alive) =
+            EventSender::new(self.backpressure, self.out_buffer, dropped_laps.clone());
+        let event_rx = EventReceiver::new(event_rx, alive);
+        let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+        let event_tx = Broadcaster::new(event_tx, subscribers.clone());
+        let config = ParserConfig {
+            decoder: self.decoder().bounded_buffer(),
+            watchdog: self.watchdog,
+        };
+        let receiver_thread = Some(std::thread::spawn(move || {
+            parse_packets(
+                scan_data_rx,
+                chunk_pool_tx,
+                scan_pool_rx,
+                parser_terminator_rx,
+                event_tx,
+                restart_tx,
+                config,
+            );
+        }));
+
+        let driver_threads = DriverThreads {
+            reader_thread,
+            receiver_thread,
+            reader_terminator_tx,
+            parser_terminator_tx,
+            scan_pool_tx,
+            dropped_laps,
+            subscribers,
+        };
+
+        Ok((driver_threads, event_rx))
+    }
+}
+
+#[cfg(all(feature = "tokio", unix))]
+impl DriverBuilder {
+    /// Opens the serial port and returns the laps as an async stream.
+    /// Must be called from within a tokio runtime.
+    ///
+    /// The settings of the driver threads (buffers, backpressure, reconnection and
+    /// watchdog) do not apply to the stream.
+    pub async fn build_async(self) -> Result<crate::async_driver::ScanStream, YDLidarError> {
+        self.validate()?;
+
+        let port = crate::async_driver::AsyncSerialPort::open(&self.port_name, self.baud_rate)?;
+        crate::async_driver::ScanStream::start(port, self.decoder(), self.data_timeout).await
+    }
+
+    /// Returns the laps of a port that is already open as an async stream, e.g. after
+    /// querying the device with `async_driver::check_device_health`. The port keeps the
+    /// baud rate it was opened with.
+    pub async fn build_async_with_port(
+        self,
+        port: crate::async_driver::AsyncSerialPort,
+    ) -> Result<crate::async_driver::ScanStream, YDLidarError> {
+        self.validate()?;
+
+        crate::async_driver::ScanStream::start(port, self.decoder(), self.data_timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::DriverEvent;
+    use crate::test_util::{answer_scan, KeepInput, LAP_START, SCAN_RESPONSE};
+    use crate::time::sleep_ms;
+    use crate::transport::MemoryTransport;
+    use crate::watchdog::Stall;
+    use serialport::{SerialPort, TTYPort};
+    use std::io::Write;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_model_defaults() {
+        let builder = DriverBuilder::new("/dev/ttyUSB0", YdlidarModel::X2);
+        assert_eq!(builder.min_distance, 1);
+        assert_eq!(builder.max_distance, 8000);
+
+        let builder = DriverBuilder::new("/dev/ttyUSB0", YdlidarModel::TMiniPro);
+        assert_eq!(builder.max_distance, 12000);
+        assert!(builder.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate() {
+        let builder = DriverBuilder::new("/dev/ttyUSB0", YdlidarModel::X2)
+            .min_distance(100)
+            .max_distance(50);
+        assert!(matches!(
+            builder.validate(),
+            Err(YDLidarError::InvalidConfiguration(_))
+        ));
+
+        let builder = DriverBuilder::new("/dev/ttyUSB0", YdlidarModel::X2).scan_buffer(0);
+        assert!(matches!(
+            builder.validate(),
+            Err(YDLidarError::InvalidConfiguration(_))
+        ));
+
+        let builder = DriverBuilder::new("/dev/ttyUSB0", YdlidarModel::X2).out_buffer(0);
+        assert!(matches!(
+            builder.build(),
+            Err(YDLidarError::InvalidConfiguration(_))
+        ));
+
+        let builder = DriverBuilder::new("/dev/ttyUSB0", YdlidarModel::X2).baud_rate(0);
+        assert!(matches!(
+            builder.validate(),
+            Err(YDLidarError::InvalidBaudRate(0))
+        ));
+
+        let policy = ReconnectPolicy {
+            max_attempts: None,
+            initial_backoff: 0,
+            max_backoff: 100,
+        };
+        let builder = DriverBuilder::new("/dev/ttyUSB0", YdlidarModel::X2).reconnect(policy);
+        assert!(matches!(
+            builder.validate(),
+            Err(YDLidarError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let builder = DriverBuilder::new("/dev/ydlidar-does-not-exist", YdlidarModel::X2);
+        assert!(matches!(
+            builder.build(),
+            Err(YDLidarError::PortNotFound(_))
+        ));
+
+        let (_master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let name = slave.name().unwrap();
+        let builder = DriverBuilder::new(&name, YdlidarModel::X2).data_timeout(50);
+        assert!(matches!(
+            builder.build(),
+            Err(YDLidarError::NoDataReceived(50))
+        ));
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        answer_scan(&mut master);
+
+        let watchdog = Watchdog {
+            packet_timeout: Some(50),
+            lap_timeout: None,
+            restart_scan: true,
+        };
+        let name = slave.name().unwrap();
+        let (thread, event_rx) = DriverBuilder::new(&name, YdlidarModel::X2)
+            .read_timeout(10)
+            .watchdog(watchdog)
+            .build_with_transport(Box::new(KeepInput::open(&name, YdlidarModel::X2)))
+            .unwrap();
+
+        assert!(matches!(
+            event_rx.recv().unwrap(),
+            DriverEvent::Stalled(Stall::NoPacket(50))
+        ));
+
+        drop(thread);
+    }
+
+    #[test]
+    fn test_watchdog_restart() {
+        let (mut device, host) = MemoryTransport::pair();
+        device.write_all(&SCAN_RESPONSE).unwrap();
+        let transport = KeepInput::open(host, YdlidarModel::X2);
+        let flushes = transport.flushes();
+
+        let watchdog = Watchdog {
+            packet_timeout: Some(50),
+            lap_timeout: None,
+            restart_scan: true,
+        };
+        let (thread, event_rx) = DriverBuilder::new("memory", YdlidarModel::X2)
+            .read_timeout(10)
+            .watchdog(watchdog)
+            .build_with_transport(Box::new(transport))
+            .unwrap();
+
+        // The scan was reset once when the driver started
+        let n_flushes = flushes.load(Ordering::SeqCst);
+        assert!(n_flushes > 0);
+        assert!(matches!(
+            event_rx.recv().unwrap(),
+            DriverEvent::Stalled(Stall::NoPacket(50))
+        ));
+        let deadline = Instant::now() + Duration::from_secs(1);
+        // The restart runs the same reset sequence
+        while flushes.load(Ordering::SeqCst) < 2 * n_flushes && Instant::now() < deadline {
+            sleep_ms(1);
+        }
+        assert!(flushes.load(Ordering::SeqCst) >= 2 * n_flushes);
+
+        drop(thread);
+    }
+
+    #[test]
+    fn test_latest_only() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        answer_scan(&mut master);
+
+        let name = slave.name().unwrap();
+        let (thread, event_rx) = DriverBuilder::new(&name, YdlidarModel::TMiniPro)
+            .read_timeout(10)
+            .backpressure(BackpressurePolicy::LatestOnly)
+            .build_with_transport(Box::new(KeepInput::open(&name, YdlidarModel::TMiniPro)))
+            .unwrap();
+
+        // Each lap start packet completes a lap
+        for _ in 0..4 {
+            master.write_all(&LAP_START).unwrap();
+        }
+        sleep_ms(100);
+
+        let DriverEvent::Scan(scan) = event_rx.try_recv().unwrap() else {
+            panic!("Expected a scan");
+        };
+        assert_eq!(scan.distances.len(), 1);
+        assert!(event_rx.try_recv().is_err());
+        assert_eq!(thread.dropped_laps(), 3);
+
+        drop(thread);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::constants::{LIDAR_ANS_LENGTH_DEVINFO, READ_CHUNK_SIZE};
+use crate::error::YDLidarError;
+use crate::packet::{device_info_bytes, parse_device_info};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use ydlidar_data::{DeviceInfo, YdlidarModel};
+
+const MAGIC: &[u8; 4] = b"YDLC";
+const VERSION: u16 = 1;
+const CHUNK_HEADER_SIZE: usize = 12;
+/// Largest chunk of a capture, the most the driver reads at once.
+const MAX_CHUNK_SIZE: usize = READ_CHUNK_SIZE;
+
+/// Description of the device a capture was recorded from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureHeader {
+    pub model: YdlidarModel,
+    /// Baud rate of the link the bytes were read from.
+    pub baud_rate: u32,
+    /// `None` when the device information was not known to the driver.
+    pub device_info: Option<DeviceInfo>,
+}
+
+/// Writes the chunks read from a device into a capture file.
+///
+/// The file starts with a header:
+///
+/// | Bytes | Content                                                     |
+/// |-------|-------------------------------------------------------------|
+/// | 4     | Magic number `YDLC`                                         |
+/// | 2     | Format version, little endian                               |
+/// | 1     | Model number, see `YdlidarModel`                            |
+/// | 4     | Baud rate, little endian                                    |
+/// | 1     | 1 if the device information follows, 0 otherwise            |
+/// | 20    | Device information, as answered by the device (optional)    |
+///
+/// followed by the chunks read from the device, each made of:
+///
+/// | Bytes | Content                                                       |
+/// |-------|---------------------------------------------------------------|
+/// | 8     | Time of the read in nanoseconds since the capture started, LE |
+/// | 4     | Number of bytes, at most 1024, little endian                  |
+/// | n     | Bytes                                                         |
+pub struct CaptureWriter {
+    file: File,
+    start: Instant,
+    record: Vec<u8>,
+}
+
+impl CaptureWriter {
+    /// Creates the capture file, replacing any existing file, and writes its header.
+    /// Timestamps count from this call.
+    pub fn create(path: &Path, header: &CaptureHeader) -> Result<Self, YDLidarError> {
+        let mut file = File::create(path)?;
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.push(header.model as u8);
+        bytes.extend(header.baud_rate.to_le_bytes());
+        match &header.device_info {
+            Some(info) => {
+                bytes.push(1);
+                bytes.extend(device_info_bytes(info));
+            }
+            None => bytes.push(0),
+        }
+        file.write_all(&bytes)?;
+        Ok(CaptureWriter {
+            file,
+            start: Instant::now(),
+            record: Vec::new(),
+        })
+    }
+
+    /// Appends a chunk stamped with the current time. Larger chunks than the driver
+    /// reads at once are split into several chunks of the same time.
+    /// Each chunk is written with a single call so that a crash loses at most the chunk
+    /// being written.
+    pub fn write_chunk(&mut self, bytes: &[u8]) -> Result<(), YDLidarError> {
+        let timestamp = self.start.elapsed().as_nanos() as u64;
+        self.record.clear();
+        for piece in bytes.chunks(MAX_CHUNK_SIZE) {
+            self.record.extend(timestamp.to_le_bytes());
+            self.record.extend((piece.len() as u32).to_le_bytes());
+            self.record.extend(piece);
+        }
+        self.file.write_all(&self.record)?;
+        Ok(())
+    }
+}
+
+/// Reads the chunks of a capture file back.
+pub struct CaptureReader {
+    reader: BufReader<File>,
+    header: CaptureHeader,
+    data_start: u64,
+}
+
+impl CaptureReader {
+    /// Opens a capture file and reads its header.
+    pub fn open(path: &Path) -> Result<Self, YDLidarError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut prefix = [0u8; 12];
+        read_header(&mut reader, &mut prefix)?;
+        if &prefix[0..4] != MAGIC {
+            return Err(YDLidarError::InvalidCapture(
+                "missing magic number".to_string(),
+            ));
+        }
+        let version = u16::from_le_bytes([prefix[4], prefix[5]]);
+        if version != VERSION {
+            return Err(YDLidarError::InvalidCapture(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let model = YdlidarModel::try_from(prefix[6])
+            .map_err(|_| YDLidarError::UnsupportedModel(prefix[6]))?;
+        let baud_rate = u32::from_le_bytes(prefix[7..11].try_into().unwrap());
+        let device_info = match prefix[11] {
+            0 => None,
+            1 => {
+                let mut info = [0u8; LIDAR_ANS_LENGTH_DEVINFO as usize];
+                read_header(&mut reader, &mut info)?;
+                Some(parse_device_info(&info))
+            }
+            flag => {
+                return Err(YDLidarError::InvalidCapture(format!(
+                    "invalid device information flag {}",
+                    flag
+                )))
+            }
+        };
+        let data_start = reader.stream_position()?;
+        Ok(CaptureReader {
+            reader,
+            header: CaptureHeader {
+                model,
+                baud_rate,
+                device_info,
+            },
+            data_start,
+        })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    /// Reads the next chunk into `bytes` and returns its timestamp, or `None` at the end
+    /// of the file. A chunk cut short by the end of the file is ignored, since it was
+    /// being written when the capture stopped.
+    pub fn next_chunk(&mut self, bytes: &mut Vec<u8>) -> Result<Option<Duration>, YDLidarError> {
+        let mut chunk_header = [0u8; CHUNK_HEADER_SIZE];
+        if !read_or_eof(&mut self.reader, &mut chunk_header)? {
+            return Ok(None);
+        }
+        let timestamp = u64::from_le_bytes(chunk_header[0..8].try_into().unwrap());
+        let len = u32::from_le_bytes(chunk_header[8..12].try_into().unwrap()) as usize;
+        // A corrupted length must not lead to a huge allocation
+        if len > MAX_CHUNK_SIZE {
+            return Err(YDLidarError::InvalidCapture(format!(
+                "chunk of {} bytes",
+                len
+            )));
+        }
+        bytes.resize(len, 0);
+        if !read_or_eof(&mut self.reader, bytes)? {
+            bytes.clear();
+            return Ok(None);
+        }
+        Ok(Some(Duration::from_nanos(timestamp)))
+    }
+
+    /// Goes back to the first chunk.
+    pub fn rewind(&mut self) -> Result<(), YDLidarError> {
+        self.reader.seek(SeekFrom::Start(self.data_start))?;
+        Ok(())
+    }
+}
+
+fn read_header(reader: &mut impl Read, buf: &mut [u8]) -> Result<(), YDLidarError> {
+    if !read_or_eof(reader, buf)? {
+        return Err(YDLidarError::InvalidCapture("truncated header".to_string()));
+    }
+    Ok(())
+}
+
+/// Fills `buf`, or returns false if the end of the file comes first.
+fn read_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool, YDLidarError> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DriverBuilder;
+    use crate::event::DriverEvent;
+    use crate::test_util::{KeepInput, LAP_START};
+    use crate::transport::MemoryTransport;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ydlidar-{}-{}.ydlc", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let path = temp_path("capture");
+        let header = CaptureHeader {
+            model: YdlidarModel::TMiniPro,
+            baud_rate: 230400,
+            device_info: Some(DeviceInfo {
+                model_number: 150,
+                firmware_major_version: 1,
+                firmware_minor_version: 2,
+                hardware_version: 3,
+                serial_number: [7; 16],
+            }),
+        };
+        let mut writer = CaptureWriter::create(&path, &header).unwrap();
+        writer.write_chunk(&[0xAA, 0x55]).unwrap();
+        writer.write_chunk(&[0x01]).unwrap();
+        writer.write_chunk(&[0x02; 1500]).unwrap();
+        drop(writer);
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert_eq!(reader.header(), &header);
+        let mut bytes = Vec::new();
+        let first = reader.next_chunk(&mut bytes).unwrap().unwrap();
+        assert_eq!(bytes, [0xAA, 0x55]);
+        let second = reader.next_chunk(&mut bytes).unwrap().unwrap();
+        assert_eq!(bytes, [0x01]);
+        assert!(first <= second);
+        // Larger chunks than the driver reads are split
+        let third = reader.next_chunk(&mut bytes).unwrap().unwrap();
+        assert_eq!(bytes, [0x02; 1024]);
+        assert_eq!(reader.next_chunk(&mut bytes).unwrap(), Some(third));
+        assert_eq!(bytes, [0x02; 476]);
+        assert!(reader.next_chunk(&mut bytes).unwrap().is_none());
+
+        reader.rewind().unwrap();
+        assert_eq!(reader.next_chunk(&mut bytes).unwrap(), Some(first));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_capture() {
+        let path = temp_path("invalid-capture");
+        std::fs::write(&path, b"YDLC\x02\x00").unwrap();
+        assert!(matches!(
+            CaptureReader::open(&path),
+            Err(YDLidarError::InvalidCapture(_))
+        ));
+
+        // A chunk length far beyond what the driver reads
+        let header = CaptureHeader {
+            model: YdlidarModel::X2,
+            baud_rate: 115200,
+            device_info: None,
+        };
+        drop(CaptureWriter::create(&path, &header).unwrap());
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(&[0; 8]).unwrap();
+        file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        drop(file);
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert!(matches!(
+            reader.next_chunk(&mut Vec::new()),
+            Err(YDLidarError::InvalidCapture(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_capture_driver() {
+        let path = temp_path("capture-driver");
+        let (mut device, host) = MemoryTransport::pair();
+        device.write_all(&LAP_START).unwrap();
+
+        let (thread, event_rx) = DriverBuilder::new("memory", YdlidarModel::TMiniPro)
+            .read_timeout(10)
+            .capture(&path)
+            .build_with_transport(Box::new(KeepInput::open(host, YdlidarModel::TMiniPro)))
+            .unwrap();
+        assert!(matches!(event_rx.recv().unwrap(), DriverEvent::Scan(_)));
+        device.write_all(&LAP_START[..5]).unwrap();
+        device.write_all(&LAP_START[5..]).unwrap();
+        assert!(matches!(event_rx.recv().unwrap(), DriverEvent::Scan(_)));
+        drop(thread);
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert_eq!(reader.header().model, YdlidarModel::TMiniPro);
+        assert_eq!(reader.header().baud_rate, 230400);
+        assert_eq!(reader.header().device_info, None);
+        let mut recorded: Vec<u8> = Vec::new();
+        let mut bytes = Vec::new();
+        while reader.next_chunk(&mut bytes).unwrap().is_some() {
+            recorded.extend(&bytes);
+        }
+        assert_eq!(recorded, [LAP_START, LAP_START].concat());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -16,5 +16,9 @@ pub(crate) const LIDAR_ANS_LENGTH_DEVHEALTH: u8 = 3;
 #[allow(dead_code)] // Temporary fix until feature flags to select ydlidar
 pub(crate) const LIDAR_ANS_TYPE_MEASUREMENT: u8 = 0x81;
 pub(crate) const N_READ_TRIALS: usize = 3;
-// Specific for each lidar
+// Distance limit of the legacy `run_driver`, whatever the model
 pub(crate) const LIDAR_MAX_DISTANCE_VALUE: u16 = 8000;
+pub(crate) const READ_CHUNK_SIZE: usize = 1024;
+pub(crate) const MAX_PACKET_SIZE: usize = PACKET_HEADER_SIZE + u8::MAX as usize * 3;
+pub(crate) const RING_BUFFER_SIZE: usize = 4096;
+pub(crate) const CONNECT_TIMEOUT: u64 = 1000;
@@ -0,0 +1,650 @@
+use crate::constants::{MAX_PACKET_SIZE, RING_BUFFER_SIZE};
+use crate::numeric::{correct_angle, degree_to_radian, to_angle};
+use crate::packet::{
+    err_if_checksum_mismatched, find_packet, is_beginning_of_cycle, lap_scan_frequency,
+    n_scan_samples, sample_angle, PacketSearch, SampleFormat,
+};
+use crate::ring_buffer::RingBuffer;
+use crate::scan::YdLidarScan;
+use std::time::{Instant, SystemTime};
+use ydlidar_data::{model_max_distance, Scan, YdlidarModel};
+
+/// Packet decoded by a [`ScanDecoder`].
+#[derive(Debug)]
+pub struct Packet<'a> {
+    bytes: &'a [u8],
+    checksum_correct: bool,
+}
+
+impl Packet<'_> {
+    /// Raw bytes of the packet, header included.
+    pub fn bytes(&self) -> &[u8] {
+        self.bytes
+    }
+
+    /// Checksum validation result of the packet.
+    pub fn checksum_correct(&self) -> bool {
+        self.checksum_correct
+    }
+
+    /// True if the packet is the first one of a lap.
+    pub fn is_lap_start(&self) -> bool {
+        is_beginning_of_cycle(self.bytes)
+    }
+
+    /// Number of samples in the packet.
+    pub fn n_samples(&self) -> usize {
+        n_scan_samples(self.bytes)
+    }
+
+    /// Angle of the first sample in degrees, as sent by the device.
+    pub fn start_angle(&self) -> f64 {
+        to_angle(self.bytes[4], self.bytes[5])
+    }
+
+    /// Angle of the last sample in degrees, as sent by the device.
+    pub fn end_angle(&self) -> f64 {
+        to_angle(self.bytes[6], self.bytes[7])
+    }
+}
+
+/// Item produced by a [`ScanDecoder`].
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)] // Boxing would add an allocation per lap
+pub enum Decoded<'a> {
+    /// A packet whose points were added to the current lap.
+    Packet(Packet<'a>),
+    /// A completed lap. It is produced before the packet that starts the next lap,
+    /// or once the lap holds `send_after` points.
+    Scan(Scan),
+}
+
+/// What the driver does with the points of a packet that fails the checksum.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumPolicy {
+    /// The points are added to the lap like any other.
+    #[default]
+    Keep,
+    /// The points are added to the lap, and `Scan::point_checksum_correct` tells them
+    /// apart from the points of valid packets.
+    Mark,
+    /// The points are dropped, which suits navigation better than wrong points.
+    Drop,
+}
+
+/// Decoder of the byte stream sent by the device, free of any I/O.
+///
+/// Bytes are pushed as they come, from a serial port, a file or a socket, and the
+/// decoded packets and laps are pulled out.
+///
+/// ```
+/// use ydlidar_data::YdlidarModel;
+/// use ydlidar_driver::{Decoded, ScanDecoder};
+///
+/// let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro);
+/// decoder.push(&[0xAA, 0x55, 0xC7, 0x01, 0x01, 0x15, 0x01, 0x15, 0x1B, 0x56]);
+/// decoder.push(&[0x14, 0x62, 0x02]);
+/// while let Some(decoded) = decoder.next() {
+///     match decoded {
+///         Decoded::Packet(packet) => assert!(packet.checksum_correct()),
+///         Decoded::Scan(scan) => println!("{} points", scan.distances.len()),
+///     }
+/// }
+/// ```
+pub struct ScanDecoder {
+    model: YdlidarModel,
+    format: SampleFormat,
+    buffer: RingBuffer,
+    packet: [u8; MAX_PACKET_SIZE],
+    /// Length and checksum result of the last decoded packet, until it is produced.
+    pending_packet: Option<(usize, bool)>,
+    scan: Scan,
+    spare_scan: Option<Scan>,
+    min_distance: u16,
+    max_distance: u16,
+    send_after: usize,
+    checksum_policy: ChecksumPolicy,
+    discarded_bytes: u64,
+    /// Reception time of the last pushed bytes, if known.
+    received: Option<(Instant, SystemTime)>,
+    /// Sequence number of the next lap produced.
+    sequence: u64,
+    /// Value of `discarded_bytes` when the current lap started.
+    lap_discarded_bytes: u64,
+    /// True if the current lap started with a lap start packet, i.e. it is a full lap.
+    lap_started: bool,
+}
+
+impl ScanDecoder {
+    /// Creates a decoder for the packet format and the distance limits of the given model.
+    pub fn new(model: YdlidarModel) -> Self {
+        ScanDecoder {
+            model,
+            format: SampleFormat::of(model),
+            buffer: RingBuffer::growable(RING_BUFFER_SIZE),
+            packet: [0; MAX_PACKET_SIZE],
+            pending_packet: None,
+            scan: Scan::new(),
+            spare_scan: None,
+            min_distance: 1,
+            max_distance: model_max_distance(model),
+            send_after: 0,
+            checksum_policy: ChecksumPolicy::Keep,
+            discarded_bytes: 0,
+            received: None,
+            sequence: 0,
+            lap_discarded_bytes: 0,
+            lap_started: false,
+        }
+    }
+
+    /// Minimum distance to keep points (inclusive). Defaults to 1.
+    pub fn min_distance(mut self, min_distance: u16) -> Self {
+        self.min_distance = min_distance;
+        self
+    }
+
+    /// Maximum distance to keep points (inclusive). Defaults to the range of the model.
+    pub fn max_distance(mut self, max_distance: u16) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    /// Produces a lap once it holds this many points, even if it is not complete.
+    /// 0 produces laps at the beginning of each lap only.
+    pub fn send_after(mut self, send_after: usize) -> Self {
+        self.send_after = send_after;
+        self
+    }
+
+    /// What to do with the points of packets that fail the checksum.
+    /// Defaults to `ChecksumPolicy::Keep`.
+    pub fn checksum_policy(mut self, policy: ChecksumPolicy) -> Self {
+        self.checksum_policy = policy;
+        self
+    }
+
+    /// Appends bytes received from the device, at an unknown time.
+    /// The decoder keeps every byte until it is decoded, whatever the size of the slices.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.received = None;
+        self.discarded_bytes += self.buffer.push_slice(bytes) as u64;
+    }
+
+    /// Same as [`push`](Self::push), for bytes received at the given host monotonic and
+    /// system times. The packets completed by these bytes are stamped with these times
+    /// in `Scan`, so the items should be pulled out before pushing more bytes.
+    pub fn push_at(&mut self, bytes: &[u8], instant: Instant, system_time: SystemTime) {
+        self.push(bytes);
+        self.received = Some((instant, system_time));
+    }
+
+    /// Decodes the next item. Returns `None` when more bytes are needed.
+    #[allow(clippy::should_implement_trait)] // The items borrow the decoder
+    pub fn next(&mut self) -> Option<Decoded<'_>> {
+        if let Some((n_packet_bytes, checksum_correct)) = self.pending_packet.take() {
+            return Some(Decoded::Packet(Packet {
+                bytes: &self.packet[..n_packet_bytes],
+                checksum_correct,
+            }));
+        }
+
+        let (start_index, n_packet_bytes) =
+            match find_packet(&self.buffer, self.format, &mut self.packet) {
+                PacketSearch::Found { start, size } => (start, size),
+                PacketSearch::Incomplete { garbage } => {
+                    self.discard(garbage);
+                    return None;
+                }
+            };
+        self.discard(start_index);
+        self.buffer.consume(n_packet_bytes);
+        let packet = &self.packet[..n_packet_bytes];
+
+        let lap_start = is_beginning_of_cycle(packet);
+        let completed = if lap_start
+            || (self.send_after != 0 && self.scan.angles_radian.len() >= self.send_after)
+        {
+            let next = self.spare_scan.take().unwrap_or_else(Scan::new);
+            let mut scan = std::mem::replace(&mut self.scan, next);
+            scan.sequence = self.sequence;
+            self.sequence += 1;
+            scan.discarded_bytes = self.discarded_bytes - self.lap_discarded_bytes;
+            self.lap_discarded_bytes = self.discarded_bytes;
+            // A full lap lasts from its lap start packet to the next one
+            if scan.scan_frequency.is_none() && self.lap_started && lap_start {
+                if let (Some(first), Some((now, _))) = (scan.first_packet_instant, self.received) {
+                    let duration = now.duration_since(first).as_secs_f64();
+                    if duration > 0. {
+                        scan.scan_frequency = Some(1. / duration);
+                    }
+                }
+            }
+            self.lap_started = lap_start;
+            Some(scan)
+        } else {
+            None
+        };
+
+        if let Some((instant, system_time)) = self.received {
+            self.scan.first_packet_instant.get_or_insert(instant);
+            self.scan.first_packet_time.get_or_insert(system_time);
+            self.scan.last_packet_instant = Some(instant);
+            self.scan.last_packet_time = Some(system_time);
+        }
+        if lap_start {
+            self.scan.scan_frequency = lap_scan_frequency(packet);
+        }
+
+        let checksum_correct = err_if_checksum_mismatched(packet, self.format).is_ok();
+        if checksum_correct {
+            self.scan.packets_accepted += 1;
+        } else {
+            self.scan.checksum_correct = false;
+            self.scan.packets_rejected += 1;
+        }
+        if checksum_correct || self.checksum_policy != ChecksumPolicy::Drop {
+            let mark = (self.checksum_policy == ChecksumPolicy::Mark).then_some(checksum_correct);
+            push_packet(
+                &mut self.scan,
+                packet,
+                self.model,
+                self.min_distance,
+                self.max_distance,
+                mark,
+            );
+        }
+
+        match completed {
+            Some(scan) => {
+                self.pending_packet = Some((n_packet_bytes, checksum_correct));
+                Some(Decoded::Scan(scan))
+            }
+            None => Some(Decoded::Packet(Packet {
+                bytes: &self.packet[..n_packet_bytes],
+                checksum_correct,
+            })),
+        }
+    }
+
+    /// Decodes items until a lap is completed. Returns `None` when more bytes are needed.
+    pub fn next_scan(&mut self) -> Option<Scan> {
+        loop {
+            if let Decoded::Scan(scan) = self.next()? {
+                return Some(scan);
+            }
+        }
+    }
+
+    /// Hands a scan back so that its storage is reused for a later lap
+    /// instead of being allocated again.
+    pub fn recycle(&mut self, mut scan: Scan) {
+        scan.reset();
+        self.spare_scan = Some(scan);
+    }
+
+    /// Number of bytes dropped so far because they were not part of a valid packet.
+    pub fn discarded_bytes(&self) -> u64 {
+        self.discarded_bytes
+    }
+
+    fn discard(&mut self, n: usize) {
+        self.buffer.consume(n);
+        self.discarded_bytes += n as u64;
+    }
+
+    /// Keeps a bounded number of bytes instead: the oldest ones are dropped, and
+    /// counted in `discarded_bytes`, if the decoded items are not pulled out in time.
+    /// Used by the driver threads, whose memory use must not grow with a stalled parser.
+    pub(crate) fn bounded_buffer(mut self) -> Self {
+        self.buffer = RingBuffer::with_capacity(RING_BUFFER_SIZE);
+        self
+    }
+
+    /// True if the next lap will be stored in a newly allocated scan.
+    pub(crate) fn needs_scan(&self) -> bool {
+        self.spare_scan.is_none()
+    }
+
+    /// Drops the buffered bytes and the current lap, e.g. after the device was reopened.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.pending_packet = None;
+        self.scan.reset();
+        self.lap_discarded_bytes = self.discarded_bytes;
+        self.lap_started = false;
+    }
+}
+
+/// Appends the points of a packet to the scan, along with `checksum_correct` for each
+/// point if it is given.
+fn push_packet(
+    scan: &mut Scan,
+    packet: &[u8],
+    model: YdlidarModel,
+    min_distance: u16,
+    max_distance: u16,
+    checksum_correct: Option<bool>,
+) {
+    let format = SampleFormat::of(model);
+    let n = n_scan_samples(packet);
+    let start_angle = -to_angle(packet[4], packet[5]);
+    let end_angle = -to_angle(packet[6], packet[7]);
+    let mut intensities = format
+        .has_intensity()
+        .then(|| scan.intensities.get_or_insert_with(Vec::new));
+    let mut flags = format
+        .has_intensity()
+        .then(|| scan.interference_flags.get_or_insert_with(Vec::new));
+    for idx in 0..n {
+        let sample = format.sample(packet, idx);
+        let flag = format.interference_flag(sample);
+        if let Some(flag) = flag {
+            scan.interference_counts.add(flag);
+        }
+        let d = format.distance(sample);
+        if d > max_distance || d < min_distance {
+            continue;
+        }
+        scan.distances.push(d);
+        if let (Some(intensities), Some(intensity)) = (&mut intensities, format.intensity(sample)) {
+            intensities.push(intensity);
+        }
+        if let (Some(flags), Some(flag)) = (&mut flags, flag) {
+            flags.push(flag);
+        }
+        let angle_degree = sample_angle(start_angle, end_angle, n, idx);
+        let angle_degree = correct_angle(model, angle_degree, d);
+        scan.angles_radian.push(degree_to_radian(angle_degree));
+        if let Some(checksum_correct) = checksum_correct {
+            scan.point_checksum_correct.push(checksum_correct);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::PacketEncoder;
+    use crate::test_util::LAP_START;
+    use std::time::Duration;
+
+    const LAP_DATA: [u8; 58] = [
+        0xAA, 0x55, 0xB0, 0x10, 0x81, 0x16, 0x01, 0x2D, 0x57, 0x7D, 0xDD, 0x76, 0x03, 0xD4, 0x76,
+        0x03, 0xC3, 0x72, 0x03, 0xB3, 0x7B, 0x03, 0x8E, 0x8A, 0x03, 0x97, 0x6E, 0x04, 0x9C, 0x22,
+        0x05, 0xA7, 0x6A, 0x05, 0xAB, 0x7A, 0x05, 0x93, 0x82, 0x05, 0x6D, 0xC2, 0x05, 0x55, 0xA6,
+        0x05, 0x57, 0x16, 0x05, 0x67, 0x62, 0x02, 0x80, 0x16, 0x02, 0x9B, 0xE6, 0x01,
+    ];
+
+    #[test]
+    fn test_next() {
+        let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro);
+        // Bytes are pushed one by one, with leading garbage
+        let bytes = [&[0x01, 0x02][..], &LAP_START, &LAP_DATA, &LAP_START].concat();
+        let mut decoded = Vec::new();
+        for byte in bytes {
+            decoder.push(&[byte]);
+            while let Some(item) = decoder.next() {
+                decoded.push(match item {
+                    Decoded::Packet(packet) => {
+                        assert!(packet.checksum_correct());
+                        (packet.is_lap_start(), packet.n_samples())
+                    }
+                    Decoded::Scan(scan) => {
+                        assert!(scan.checksum_correct);
+                        (true, scan.distances.len() + 1000)
+                    }
+                });
+            }
+        }
+        // A lap is produced before the packet that starts the next one
+        assert_eq!(
+            decoded,
+            [
+                (true, 1000),
+                (true, 1),
+                (false, 16),
+                (true, 1017),
+                (true, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_next_scan() {
+        let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro).send_after(10);
+        decoder.push(&LAP_START);
+        decoder.push(&LAP_DATA);
+        decoder.push(&LAP_DATA);
+        assert!(decoder.next_scan().unwrap().distances.is_empty());
+        // The lap was cut because it reached `send_after` points
+        let scan = decoder.next_scan().unwrap();
+        assert_eq!(scan.distances.len(), 17);
+        assert!(decoder.next_scan().is_none());
+
+        decoder.recycle(scan);
+        decoder.push(&LAP_START);
+        let scan = decoder.next_scan().unwrap();
+        assert_eq!(scan.distances.len(), 16);
+        decoder.push(&LAP_START);
+        let scan = decoder.next_scan().unwrap();
+        assert!(scan.distances.capacity() >= 17);
+        assert_eq!(scan.distances.len(), 1);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro);
+        let mut corrupted = LAP_DATA;
+        corrupted[20] ^= 0xFF;
+        decoder.push(&LAP_START);
+        decoder.push(&corrupted);
+        decoder.push(&LAP_START);
+        assert!(decoder.next_scan().is_some());
+        assert!(matches!(
+            decoder.next(),
+            Some(Decoded::Packet(packet)) if packet.checksum_correct()
+        ));
+        assert!(matches!(
+            decoder.next(),
+            Some(Decoded::Packet(packet)) if !packet.checksum_correct()
+        ));
+        assert!(!decoder.next_scan().unwrap().checksum_correct);
+
+        decoder.reset();
+        assert!(decoder.next().is_none());
+    }
+
+    #[test]
+    fn test_sample_formats() {
+        let decode = |model| {
+            let encoder = PacketEncoder::new(model);
+            let packet = encoder.scan_packet(false, 10., 20., &[100, 2000, 12000]);
+            let mut decoder = ScanDecoder::new(model).max_distance(12000);
+            decoder.push(&encoder.scan_packet(true, 0., 0., &[0]));
+            decoder.push(&packet);
+            decoder.push(&encoder.scan_packet(true, 0., 0., &[0]));
+            decoder.next_scan().unwrap();
+            (packet.len(), decoder.next_scan().unwrap())
+        };
+
+        let (size, scan) = decode(YdlidarModel::X2);
+        assert_eq!(size, 10 + 3 * 2);
+        assert_eq!(scan.distances, [100, 2000, 12000]);
+        // The angles of the X2 are corrected with the distance
+        let angle = |d: f64| -10. - (21.8 * (155.3 - d) / (155.3 * d)).atan().to_degrees();
+        assert!((scan.angles_radian[0] - degree_to_radian(angle(100.))).abs() < 1e-12);
+
+        let (size, scan) = decode(YdlidarModel::TMiniPro);
+        assert_eq!(size, 10 + 3 * 3);
+        assert_eq!(scan.distances, [100, 2000, 12000]);
+        assert_eq!(scan.angles_radian[0], degree_to_radian(-10.));
+        assert_eq!(scan.angles_radian[2], degree_to_radian(-20.));
+    }
+
+    #[test]
+    fn test_intensities() {
+        let encoder = PacketEncoder::new(YdlidarModel::TMiniPro);
+        let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro).max_distance(5000);
+        decoder.push(&encoder.scan_packet_with_intensities(true, 0., 0., &[0], &[1]));
+        decoder.push(&encoder.scan_packet_with_intensities(
+            false,
+            10.,
+            20.,
+            &[100, 9000, 0, 2000],
+            &[50, 60, 70, 80],
+        ));
+        decoder.push(&encoder.scan_packet(true, 0., 0., &[0]));
+        decoder.next_scan().unwrap();
+        // The intensities of the points out of the distance limits are dropped with them
+        let scan = decoder.next_scan().unwrap();
+        assert_eq!(scan.distances, [100, 2000]);
+        assert_eq!(scan.intensities, Some(vec![50, 80]));
+
+        let encoder = PacketEncoder::new(YdlidarModel::X2);
+        let mut decoder = ScanDecoder::new(YdlidarModel::X2);
+        let lap_start = encoder.scan_packet_with_intensities(true, 0., 0., &[500], &[1]);
+        decoder.push(&[&lap_start[..], &lap_start].concat());
+        decoder.next_scan().unwrap();
+        let scan = decoder.next_scan().unwrap();
+        assert_eq!(scan.distances, [500]);
+        assert_eq!(scan.intensities, None);
+    }
+
+    #[test]
+    fn test_checksum_policy() {
+        let mut corrupted = LAP_DATA;
+        corrupted[20] ^= 0xFF;
+        let decode_lap = |policy| {
+            let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro).checksum_policy(policy);
+            decoder.push(&[&LAP_START[..], &corrupted, &LAP_DATA, &LAP_START].concat());
+            decoder.next_scan().unwrap();
+            decoder.next_scan().unwrap()
+        };
+
+        let kept = decode_lap(ChecksumPolicy::Keep);
+        assert_eq!(kept.distances.len(), 33);
+        assert!(kept.point_checksum_correct.is_empty());
+        assert!(!kept.checksum_correct);
+        assert_eq!((kept.packets_accepted, kept.packets_rejected), (2, 1));
+
+        let marked = decode_lap(ChecksumPolicy::Mark);
+        assert_eq!(marked.distances, kept.distances);
+        let expected = [vec![true], vec![false; 16], vec![true; 16]].concat();
+        assert_eq!(marked.point_checksum_correct, expected);
+        assert_eq!((marked.packets_accepted, marked.packets_rejected), (2, 1));
+
+        let dropped = decode_lap(ChecksumPolicy::Drop);
+        assert_eq!(dropped.distances.len(), 17);
+        assert!(dropped.point_checksum_correct.is_empty());
+        assert!(!dropped.checksum_correct);
+        assert_eq!((dropped.packets_accepted, dropped.packets_rejected), (2, 1));
+    }
+
+    #[test]
+    fn test_large_push() {
+        let encoder = PacketEncoder::new(YdlidarModel::TMiniPro);
+        let mut bytes = encoder.scan_packet(true, 0., 0., &[500]);
+        for _ in 0..100 {
+            bytes.extend(encoder.scan_packet(false, 1., 2., &[500; 40]));
+        }
+        bytes.extend(encoder.scan_packet(true, 0., 0., &[500]));
+        assert!(bytes.len() > RING_BUFFER_SIZE);
+
+        let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro);
+        decoder.push(&bytes);
+        decoder.next_scan().unwrap();
+        assert_eq!(decoder.next_scan().unwrap().distances.len(), 4001);
+        assert_eq!(decoder.discarded_bytes(), 0);
+
+        // The driver threads keep a bounded buffer
+        let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro).bounded_buffer();
+        decoder.push(&bytes);
+        assert!(decoder.discarded_bytes() > 0);
+    }
+
+    #[test]
+    fn test_lap_metadata() {
+        let encoder = PacketEncoder::new(YdlidarModel::X2);
+        let lap_start = encoder.scan_packet(true, 0., 0., &[500]);
+        let data = encoder.scan_packet(false, 1., 2., &[500, 500]);
+        let (instant, system_time) = (Instant::now(), SystemTime::now());
+        let after = |ms| Duration::from_millis(ms);
+        let mut decoder = ScanDecoder::new(YdlidarModel::X2);
+        decoder.push_at(&lap_start, instant, system_time);
+        assert_eq!(decoder.next_scan().unwrap().sequence, 0);
+        // Packets are stamped with the time of the bytes pushed before they are pulled out
+        decoder.push_at(&data, instant + after(50), system_time + after(50));
+        decoder.push_at(&[0x01, 0x02], instant + after(60), system_time + after(60));
+        assert!(decoder.next_scan().is_none());
+        decoder.push_at(&data, instant + after(100), system_time + after(100));
+        assert!(decoder.next_scan().is_none());
+        decoder.push_at(&lap_start, instant + after(200), system_time + after(200));
+        let scan = decoder.next_scan().unwrap();
+        assert_eq!(scan.sequence, 1);
+        assert_eq!(scan.first_packet_instant, Some(instant));
+        assert_eq!(scan.last_packet_instant, Some(instant + after(100)));
+        assert_eq!(scan.first_packet_time, Some(system_time));
+        assert_eq!(scan.last_packet_time, Some(system_time + after(100)));
+        assert_eq!(scan.n_packets(), 3);
+        assert_eq!(scan.discarded_bytes, 2);
+        // The X2 does not send its frequency: it is measured between two lap starts
+        assert!((scan.scan_frequency.unwrap() - 5.).abs() < 1e-9);
+
+        // The frequency sent in the lap start packet comes first
+        let lap_start = encoder.scan_frequency(7.).scan_packet(true, 0., 0., &[500]);
+        decoder.push_at(&lap_start, instant + after(300), system_time + after(300));
+        assert!(decoder.next_scan().is_some());
+        decoder.push(&lap_start);
+        let scan = decoder.next_scan().unwrap();
+        assert_eq!(scan.sequence, 3);
+        assert_eq!(scan.scan_frequency, Some(7.));
+        assert_eq!(scan.discarded_bytes, 0);
+        // Bytes pushed without their reception time leave the lap unstamped
+        decoder.push(&lap_start);
+        assert_eq!(decoder.next_scan().unwrap().first_packet_instant, None);
+    }
+
+    #[test]
+    fn test_resync() {
+        fn decode_all(decoder: &mut ScanDecoder) -> Vec<(bool, usize)> {
+            let mut decoded = Vec::new();
+            while let Some(item) = decoder.next() {
+                if let Decoded::Packet(packet) = item {
+                    assert!(packet.checksum_correct());
+                    decoded.push((packet.is_lap_start(), packet.n_samples()));
+                }
+            }
+            decoded
+        }
+
+        // Garbage holding a header whose checksum does not match
+        let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro);
+        let garbage = [
+            0xAA, 0x55, 0x00, 0x02, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x12, 0x34,
+        ];
+        decoder.push(&[&garbage[..], &LAP_START, &LAP_DATA, &LAP_START].concat());
+        assert_eq!(
+            decode_all(&mut decoder),
+            [(true, 1), (false, 16), (true, 1)]
+        );
+        assert_eq!(decoder.discarded_bytes(), garbage.len() as u64);
+
+        // A corrupted sample count would swallow the start of the next packet
+        let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro);
+        let mut corrupted = LAP_DATA;
+        corrupted[3] = 0x11;
+        decoder.push(&[&LAP_START[..], &corrupted, &LAP_START].concat());
+        assert_eq!(decode_all(&mut decoder), [(true, 1), (true, 1)]);
+        assert_eq!(decoder.discarded_bytes(), LAP_DATA.len() as u64);
+
+        // Bytes without any header are dropped, except a possible first header byte
+        let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro);
+        decoder.push(&[0x01, 0x02, 0xAA]);
+        assert!(decoder.next().is_none());
+        assert_eq!(decoder.discarded_bytes(), 2);
+        decoder.push(&LAP_START[1..]);
+        assert_eq!(decode_all(&mut decoder), [(true, 1)]);
+    }
+}
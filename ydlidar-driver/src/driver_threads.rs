@@ -1,16 +1,19 @@
-use crate::numeric::{calc_distance, correct_angle, degree_to_radian, to_angle};
-use crate::packet::{
-    err_if_checksum_mismatched, is_beginning_of_cycle, n_scan_samples, scan_index,
-    sendable_packet_range,
-};
-use crate::scan::YdLidarScan;
-use crate::serial::{flush, get_n_read, read, stop_scan_and_flush};
-use crate::time::sleep_ms;
-use crossbeam_channel::{Receiver, Sender};
-use serialport::SerialPort;
-use std::collections::VecDeque;
-use std::sync::mpsc;
+use crate::capture::CaptureWriter;
+use crate::constants::READ_CHUNK_SIZE;
+use crate::decoder::{Decoded, ScanDecoder};
+use crate::error::YDLidarError;
+use crate::event::DriverEvent;
+use crate::output::{subscribe, BackpressurePolicy, Broadcaster, Subscribers, Subscription};
+use crate::reconnect::{ReconnectResult, Reconnector};
+use crate::serial::{reset_scan, stop_scan_and_flush};
+use crate::transport::Transport;
+use crate::watchdog::{Watchdog, WatchdogTimer};
+use crossbeam_channel::{select, Receiver, RecvTimeoutError, Sender};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 use ydlidar_data::Scan;
 
 /// Struct that contains driver threads.
@@ -19,128 +22,270 @@ pub struct DriverThreads {
     pub(crate) parser_terminator_tx: Sender<bool>,
     pub(crate) reader_thread: Option<JoinHandle<()>>,
     pub(crate) receiver_thread: Option<JoinHandle<()>>,
+    pub(crate) scan_pool_tx: Sender<Scan>,
+    pub(crate) dropped_laps: Arc<AtomicU64>,
+    pub(crate) subscribers: Arc<Mutex<Subscribers>>,
 }
 
+impl DriverThreads {
+    /// Hands a scan back to the driver so that its storage is reused for a later lap
+    /// instead of being allocated again. The scan is dropped if the pool is already full.
+    pub fn recycle(&self, scan: Scan) {
+        let _ = self.scan_pool_tx.try_send(scan);
+    }
+
+    /// Number of laps discarded because the consumer did not keep up with the output
+    /// channel. Always 0 with `BackpressurePolicy::Block`.
+    pub fn dropped_laps(&self) -> u64 {
+        self.dropped_laps.load(Ordering::Relaxed)
+    }
+
+    /// Subscribes to the laps decoded by the driver, in addition to the event receiver.
+    /// Each subscriber has its own channel of `depth` laps and its own backpressure
+    /// policy. With `BackpressurePolicy::Block`, a subscriber that stops reading stalls
+    /// the whole driver.
+    pub fn subscribe(&self, depth: usize, policy: BackpressurePolicy) -> Subscription {
+        subscribe(&self.subscribers, depth, policy)
+    }
+}
+
+/// Message sent from the reader thread to the parser thread.
+pub(crate) enum ReaderEvent {
+    /// Bytes read from the device, in a chunk taken from the chunk pool, with the
+    /// monotonic and system times at which they were read.
+    Data(Vec<u8>, Instant, SystemTime),
+    Error(YDLidarError),
+    Reconnecting(u32),
+    Reconnected,
+    Disconnected,
+}
+
+/// Settings of the reader thread.
+pub(crate) struct ReaderConfig {
+    pub(crate) read_timeout: u64,
+    pub(crate) reconnector: Option<Reconnector>,
+    pub(crate) capture: Option<CaptureWriter>,
+}
+
+/// Settings of the parser thread.
+pub(crate) struct ParserConfig {
+    pub(crate) decoder: ScanDecoder,
+    pub(crate) watchdog: Option<Watchdog>,
+}
+
+/// Reads the device until terminated.
+/// Reads block until data arrives or `read_timeout` elapses, so termination is noticed
+/// within `read_timeout` milliseconds.
+/// Bytes are read into chunks taken from `chunk_pool_rx`, which the parser thread hands
+/// back once consumed, so no memory is allocated per read.
+/// Each chunk is also written to the capture file, if any.
 pub(crate) fn read_device_signal(
-    port: &mut Box<dyn SerialPort>,
-    scan_data_tx: mpsc::SyncSender<Vec<u8>>,
+    port: &mut dyn Transport,
+    scan_data_tx: Sender<ReaderEvent>,
+    chunk_pool_rx: Receiver<Vec<u8>>,
     reader_terminator_rx: Receiver<bool>,
-    sleep: u64,
+    restart_rx: Receiver<()>,
+    config: ReaderConfig,
 ) {
+    let ReaderConfig {
+        read_timeout,
+        reconnector,
+        mut capture,
+    } = config;
+    let mut spare_chunk = None;
+    if let Err(e) = port.set_timeout(Duration::from_millis(read_timeout)) {
+        let _ = scan_data_tx.send(ReaderEvent::Error(e));
+    }
     loop {
         if do_terminate(&reader_terminator_rx) {
             if let Err(e) = stop_scan_and_flush(port) {
-                eprintln!("{e}");
+                let _ = scan_data_tx.send(ReaderEvent::Error(e));
             }
             return;
         }
 
-        let n_read: usize = get_n_read(port).unwrap_or(0);
-        if n_read == 0 {
-            sleep_ms(sleep);
-            continue;
+        if restart_rx.try_recv().is_ok() {
+            if let Err(e) = reset_scan(port) {
+                let _ = scan_data_tx.send(ReaderEvent::Error(e));
+            }
         }
 
-        if let Ok(signal) = read(port, n_read) {
-            if let Err(e) = scan_data_tx.send(signal) {
-                eprintln!("{e}");
-                flush(port).expect("Could not flush port!");
+        let mut chunk = match spare_chunk.take() {
+            Some(chunk) => chunk,
+            None => match chunk_pool_rx.recv_timeout(Duration::from_millis(read_timeout)) {
+                Ok(chunk) => chunk,
+                // Every chunk is waiting to be parsed
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    // The parser thread is gone, nobody is left to consume the data
+                    // or to report a flush error to
+                    let _ = port.flush();
+                    return;
+                }
+            },
+        };
+        chunk.resize(READ_CHUNK_SIZE, 0);
+        let result = port.read(&mut chunk);
+        let error = match result {
+            Ok(n_read) if n_read > 0 => {
+                chunk.truncate(n_read);
+                let (instant, system_time) = (Instant::now(), SystemTime::now());
+                if let Some(writer) = capture.as_mut() {
+                    if let Err(e) = writer.write_chunk(&chunk) {
+                        // Reading goes on without the capture
+                        capture = None;
+                        let _ = scan_data_tx.send(ReaderEvent::Error(e));
+                    }
+                }
+                if scan_data_tx
+                    .send(ReaderEvent::Data(chunk, instant, system_time))
+                    .is_err()
+                {
+                    // The parser thread is gone, nobody is left to consume the data
+                    // or to report a flush error to
+                    let _ = port.flush();
+                    return;
+                }
+                continue;
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                spare_chunk = Some(chunk);
+                continue;
+            }
+            Ok(_) => YDLidarError::IoError(io::ErrorKind::UnexpectedEof.into()),
+            Err(e) => YDLidarError::IoError(e),
+        };
+        spare_chunk = Some(chunk);
+
+        // Any other outcome of a blocking read means that the device is gone
+        let _ = scan_data_tx.send(ReaderEvent::Error(error));
+        let result = match &reconnector {
+            Some(reconnector) => reconnector.reconnect(port, &scan_data_tx, &reader_terminator_rx),
+            None => ReconnectResult::GaveUp,
+        };
+        match result {
+            ReconnectResult::Reconnected => {
+                if let Err(e) = port.set_timeout(Duration::from_millis(read_timeout)) {
+                    let _ = scan_data_tx.send(ReaderEvent::Error(e));
+                }
+            }
+            ReconnectResult::GaveUp => {
+                let _ = scan_data_tx.send(ReaderEvent::Disconnected);
+                return;
             }
+            ReconnectResult::Terminated => return,
         }
     }
 }
 
+/// Parses the signal sent by the reader thread until terminated.
+/// The thread sleeps until data, a termination request or a watchdog deadline arrives.
+/// Read chunks are handed back through `chunk_pool_tx` and scans are taken from
+/// `scan_pool_rx` when available, so no memory is allocated per packet.
 pub(crate) fn parse_packets(
-    scan_data_rx: mpsc::Receiver<Vec<u8>>,
+    scan_data_rx: Receiver<ReaderEvent>,
+    chunk_pool_tx: Sender<Vec<u8>>,
+    scan_pool_rx: Receiver<Scan>,
     parser_terminator_rx: Receiver<bool>,
-    scan_tx: mpsc::SyncSender<Scan>,
-    min_distance: u16,
-    max_distance: u16,
-    send_after: usize,
-    sleep: u64,
+    mut event_tx: Broadcaster,
+    restart_tx: Sender<()>,
+    config: ParserConfig,
 ) {
-    let mut buffer = VecDeque::<u8>::new();
-    let mut scan = Scan::new();
-    while !do_terminate(&parser_terminator_rx) {
-        match scan_data_rx.try_recv() {
-            Ok(data) => buffer.extend(data),
-            Err(_) => {
-                sleep_ms(sleep);
-                continue;
+    let ParserConfig {
+        mut decoder,
+        watchdog,
+    } = config;
+    let mut watchdog = watchdog.map(WatchdogTimer::new);
+    loop {
+        // Extract every complete packet before waiting for more data
+        loop {
+            if decoder.needs_scan() {
+                if let Ok(scan) = scan_pool_rx.try_recv() {
+                    decoder.recycle(scan);
+                }
             }
-        }
-
-        if buffer.is_empty() {
-            continue;
-        }
-
-        let (start_index, n_packet_bytes) = match sendable_packet_range(&buffer) {
-            Ok(t) => t,
-            Err(_) => {
-                sleep_ms(sleep);
-                continue;
+            match decoder.next() {
+                Some(Decoded::Packet(packet)) => {
+                    if let Some(timer) = watchdog.as_mut() {
+                        if packet.checksum_correct() {
+                            timer.packet_received();
+                        }
+                        if packet.is_lap_start() {
+                            timer.lap_received();
+                        }
+                    }
+                }
+                Some(Decoded::Scan(scan)) => {
+                    if event_tx
+                        .send(DriverEvent::Scan(scan), &parser_terminator_rx)
+                        .is_err()
+                    {
+                        // Terminated, or nobody is left to receive the scans
+                        return;
+                    }
+                }
+                None => break,
             }
-        };
-        buffer.drain(..start_index); // remove leading bytes
-        if buffer.len() < n_packet_bytes {
-            // insufficient buffer size to extract a packet
-            sleep_ms(sleep);
-            continue;
-        }
-        let packet = buffer.drain(0..n_packet_bytes).collect::<Vec<_>>();
-        if is_beginning_of_cycle(&packet)
-            || (send_after != 0 && scan.angles_radian.len() >= send_after)
-        {
-            scan_tx.send(scan).unwrap();
-            scan = Scan::new();
         }
 
-        if err_if_checksum_mismatched(&packet).is_err() {
-            scan.checksum_correct = false;
+        if let Some(timer) = watchdog.as_mut() {
+            if let Some(stall) = timer.check() {
+                if timer.restart_scan() {
+                    let _ = restart_tx.try_send(());
+                }
+                if event_tx
+                    .send(DriverEvent::Stalled(stall), &parser_terminator_rx)
+                    .is_err()
+                {
+                    return;
+                }
+            }
         }
 
-        let n = n_scan_samples(&packet);
-        if n == 1 {
-            // Start Angle == End Angle
-            let dist_idx = scan_index(0);
-            let d = calc_distance(packet[dist_idx + 1], packet[dist_idx + 2]);
-            if d > max_distance || d < min_distance {
+        let timeout = watchdog
+            .as_ref()
+            .map_or(Duration::MAX, |timer| timer.time_to_next_check());
+        let event = select! {
+            recv(parser_terminator_rx) -> _ => break,
+            recv(scan_data_rx) -> event => match event {
+                Ok(event) => event,
+                // The reader thread stopped
+                Err(_) => break,
+            },
+            default(timeout) => continue,
+        };
+        let event = match event {
+            ReaderEvent::Data(chunk, instant, system_time) => {
+                decoder.push_at(&chunk, instant, system_time);
+                // The pool holds every chunk, so there is always room to hand it back
+                let _ = chunk_pool_tx.try_send(chunk);
                 continue;
             }
-            scan.distances.push(d);
-            let angle_degree = -to_angle(packet[4], packet[5]);
-            let angle_degree = correct_angle(angle_degree, d);
-            let angle_radian = degree_to_radian(angle_degree);
-            scan.angles_radian.push(angle_radian);
-        } else {
-            let start_angle = -to_angle(packet[4], packet[5]);
-            let end_angle = -to_angle(packet[6], packet[7]);
-            let angle_shift = if start_angle > end_angle { 0f64 } else { 360. };
-            let angle_diff = end_angle - start_angle + angle_shift;
-            let angle_rate: f64 = angle_diff / ((n - 1) as f64);
-            (0..n)
-                .map(|idx| (idx, scan_index(idx)))
-                .for_each(|(packet_idx, dist_idx)| {
-                    let d = calc_distance(packet[dist_idx + 1], packet[dist_idx + 2]);
-                    if d > max_distance || d < min_distance {
-                        return;
-                    }
-                    scan.distances.push(d);
-                    if packet_idx == 0 {
-                        let start_angle = correct_angle(start_angle, d);
-                        scan.angles_radian.push(degree_to_radian(start_angle));
-                    } else if packet_idx == n - 1 {
-                        let end_angle = correct_angle(end_angle, d);
-                        scan.angles_radian.push(degree_to_radian(end_angle));
-                    } else {
-                        let angle_degree = (start_angle + (packet_idx as f64) * angle_rate) % 360.;
-                        let angle_degree = correct_angle(angle_degree, d);
-                        let angle_radian = degree_to_radian(angle_degree);
-                        scan.angles_radian.push(angle_radian);
-                    }
-                });
+            ReaderEvent::Error(e) => DriverEvent::Error(e),
+            ReaderEvent::Reconnecting(attempt) => DriverEvent::Reconnecting(attempt),
+            ReaderEvent::Reconnected => {
+                // Bytes received before the disconnection cannot be completed anymore
+                decoder.reset();
+                DriverEvent::Reconnected
+            }
+            ReaderEvent::Disconnected => {
+                let _ = event_tx.send(DriverEvent::Disconnected, &parser_terminator_rx);
+                break;
+            }
+        };
+        if event_tx.send(event, &parser_terminator_rx).is_err() {
+            return;
         }
     }
+    // The consumer may have stopped reading, so this must not block
+    event_tx.try_send(DriverEvent::Stopped);
 }
 
 pub(crate) fn do_terminate(terminator_rx: &Receiver<bool>) -> bool {
@@ -150,8 +295,9 @@ pub(crate) fn do_terminate(terminator_rx: &Receiver<bool>) -> bool {
 /// Function to join driver threads.
 /// This function is automatically called when `driver_threads` is dropped.
 pub fn join(driver_threads: &mut DriverThreads) {
-    driver_threads.reader_terminator_tx.send(true).unwrap();
-    driver_threads.parser_terminator_tx.send(true).unwrap();
+    // The threads may already have stopped on their own, e.g. after a disconnection
+    let _ = driver_threads.reader_terminator_tx.send(true);
+    let _ = driver_threads.parser_terminator_tx.send(true);
 
     if driver_threads.reader_thread.is_some() {
         let thread = driver_threads.reader_thread.take().unwrap();
@@ -0,0 +1,374 @@
+use crate::constants::{
+    LIDAR_CMD_FORCE_STOP, LIDAR_CMD_GET_DEVICE_HEALTH, LIDAR_CMD_GET_DEVICE_INFO, LIDAR_CMD_SCAN,
+    LIDAR_CMD_STOP, LIDAR_CMD_SYNC_BYTE, READ_CHUNK_SIZE,
+};
+use crate::driver_threads::do_terminate;
+use crate::encoder::PacketEncoder;
+use crate::error::YDLidarError;
+use crate::replay::ReplayTransport;
+use crate::time::sleep_ms;
+use crate::transport::Transport;
+use crossbeam_channel::{bounded, Sender};
+use serialport::{SerialPort, TTYPort};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use ydlidar_data::{DeviceInfo, YdlidarModel};
+
+/// Time the emulator waits for data or commands in each iteration.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Data streamed by an `Emulator`.
+pub enum EmulatorSource {
+    /// Plays a capture file in a loop, at its original pace.
+    Capture(PathBuf),
+    /// Sends the bytes returned by `next`, which is called again after `interval`.
+    Generator {
+        next: Box<dyn FnMut() -> Vec<u8> + Send>,
+        interval: Duration,
+    },
+}
+
+impl EmulatorSource {
+    /// Laps of a round room centered on the lidar: every sample is at `distance` mm.
+    /// Each lap is made of a lap start packet and 12 packets of 40 samples, in the
+    /// format of the given model.
+    ///
+    /// Fails if `scan_frequency` is not a positive number of laps per second.
+    pub fn round_room(
+        model: YdlidarModel,
+        distance: u16,
+        scan_frequency: f64,
+    ) -> Result<Self, YDLidarError> {
+        if !(scan_frequency.is_finite() && scan_frequency > 0.) {
+            return Err(YDLidarError::InvalidConfiguration(format!(
+                "scan_frequency ({scan_frequency}) must be greater than 0"
+            )));
+        }
+        const N_PACKETS: usize = 12;
+        const N_SAMPLES: usize = 40;
+        let step = 360. / (N_PACKETS * N_SAMPLES) as f64;
+        let samples = [distance; N_SAMPLES];
+        let encoder = PacketEncoder::new(model);
+        let mut index = 0;
+        let next = move || {
+            let packet = if index == 0 {
+                encoder.scan_packet(true, 0., 0., &[distance])
+            } else {
+                let start_angle = (index - 1) as f64 * N_SAMPLES as f64 * step;
+                let end_angle = start_angle + (N_SAMPLES - 1) as f64 * step;
+                encoder.scan_packet(false, start_angle, end_angle, &samples)
+            };
+            index = (index + 1) % (N_PACKETS + 1);
+            packet
+        };
+        Ok(EmulatorSource::Generator {
+            next: Box::new(next),
+            interval: Duration::from_secs_f64(1. / scan_frequency / (N_PACKETS + 1) as f64),
+        })
+    }
+}
+
+enum Source {
+    Replay(Box<ReplayTransport>),
+    Generator {
+        next: Box<dyn FnMut() -> Vec<u8> + Send>,
+        interval: Duration,
+        next_at: Instant,
+    },
+}
+
+impl Source {
+    /// Fills `buf` with the next bytes to send, waiting at most `POLL_INTERVAL`.
+    fn poll(&mut self, buf: &mut Vec<u8>) {
+        buf.clear();
+        match self {
+            Source::Replay(replay) => {
+                buf.resize(READ_CHUNK_SIZE, 0);
+                let n_read = replay.read(buf).unwrap_or(0);
+                buf.truncate(n_read);
+            }
+            Source::Generator {
+                next,
+                interval,
+                next_at,
+            } => {
+                let now = Instant::now();
+                if now < *next_at {
+                    std::thread::sleep((*next_at - now).min(POLL_INTERVAL));
+                    return;
+                }
+                buf.extend(next());
+                *next_at += *interval;
+                // Does not try to catch up after falling behind
+                *next_at = (*next_at).max(now);
+            }
+        }
+    }
+}
+
+/// Virtual lidar on a pseudo terminal, for tools that can only open `/dev/tty*` paths.
+///
+/// The emulator answers the device information and health commands, and streams scan
+/// packets until it receives a stop command. The emulator stops when dropped, or when
+/// it cannot write to the pseudo terminal.
+///
+/// ```no_run
+/// use ydlidar_data::YdlidarModel;
+/// use ydlidar_driver::{run_driver, Emulator, EmulatorSource};
+///
+/// let source = EmulatorSource::round_room(YdlidarModel::X2, 1000, 7.).unwrap();
+/// let emulator = Emulator::start(YdlidarModel::X2, source).unwrap();
+/// let (driver_threads, event_rx) =
+///     run_driver(emulator.path(), YdlidarModel::X2, 200, 10, 0, 100).unwrap();
+/// ```
+pub struct Emulator {
+    path: String,
+    terminator_tx: Sender<bool>,
+    thread: Option<JoinHandle<Result<(), YDLidarError>>>,
+}
+
+impl Emulator {
+    /// Creates the pseudo terminal and starts acting as a lidar of the given model on it.
+    pub fn start(model: YdlidarModel, source: EmulatorSource) -> Result<Self, YDLidarError> {
+        let source = match source {
+            EmulatorSource::Capture(path) => {
+                let mut replay = ReplayTransport::open(&path)?.looping(true);
+                replay.set_timeout(POLL_INTERVAL)?;
+                Source::Replay(Box::new(replay))
+            }
+            EmulatorSource::Generator { next, interval } => Source::Generator {
+                next,
+                interval,
+                next_at: Instant::now(),
+            },
+        };
+        let (mut master, slave) = TTYPort::pair()?;
+        master.set_timeout(POLL_INTERVAL)?;
+        let path = slave
+            .name()
+            .ok_or_else(|| YDLidarError::PortNotFound(String::new()))?;
+        let device_info = DeviceInfo {
+            model_number: model as u8,
+            firmware_major_version: 1,
+            firmware_minor_version: 0,
+            hardware_version: 1,
+            serial_number: [0; 16],
+        };
+
+        let (terminator_tx, terminator_rx) = bounded(1);
+        let thread = std::thread::spawn(move || {
+            // Keeps the slave side open so that clients can come and go
+            let _slave = slave;
+            let mut device = Device {
+                master,
+                source,
+                encoder: PacketEncoder::new(model),
+                device_info,
+                streaming: true,
+                command: Vec::new(),
+            };
+            let mut buf = Vec::with_capacity(READ_CHUNK_SIZE);
+            while !do_terminate(&terminator_rx) {
+                device.answer_commands()?;
+                if device.streaming {
+                    device.source.poll(&mut buf);
+                    device.send(&buf)?;
+                } else {
+                    sleep_ms(POLL_INTERVAL.as_millis() as u64);
+                }
+            }
+            Ok(())
+        });
+
+        Ok(Emulator {
+            path,
+            terminator_tx,
+            thread: Some(thread),
+        })
+    }
+
+    /// Path of the pseudo terminal to open, such as `/dev/pts/3`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Stops the emulator. Returns the error that stopped it earlier, if any.
+    pub fn stop(mut self) -> Result<(), YDLidarError> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<(), YDLidarError> {
+        let _ = self.terminator_tx.send(true);
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or(Ok(())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+struct Device {
+    master: TTYPort,
+    source: Source,
+    encoder: PacketEncoder,
+    device_info: DeviceInfo,
+    streaming: bool,
+    /// Bytes received from the client that do not form a command yet.
+    command: Vec<u8>,
+}
+
+impl Device {
+    fn answer_commands(&mut self) -> io::Result<()> {
+        let n_read = self.master.bytes_to_read().unwrap_or(0) as usize;
+        if n_read > 0 {
+            let start = self.command.len();
+            self.command.resize(start + n_read, 0);
+            match self.master.read(&mut self.command[start..]) {
+                Ok(n) => self.command.truncate(start + n),
+                Err(_) => self.command.truncate(start),
+            }
+        }
+
+        let mut i = 0;
+        while i + 1 < self.command.len() {
+            if self.command[i] != LIDAR_CMD_SYNC_BYTE {
+                i += 1;
+                continue;
+            }
+            let response = match self.command[i + 1] {
+                LIDAR_CMD_GET_DEVICE_HEALTH => Some(self.encoder.device_health(0, 0)),
+                LIDAR_CMD_GET_DEVICE_INFO => Some(self.encoder.device_info(&self.device_info)),
+                LIDAR_CMD_SCAN => {
+                    self.streaming = true;
+                    Some(self.encoder.scan_started())
+                }
+                LIDAR_CMD_STOP | LIDAR_CMD_FORCE_STOP => {
+                    self.streaming = false;
+                    None
+                }
+                _ => None,
+            };
+            if let Some(response) = response {
+                self.send(&response)?;
+            }
+            i += 2;
+        }
+        // A sync byte at the end may be followed by its command in the next read
+        self.command.drain(..i);
+        Ok(())
+    }
+
+    /// Bytes the client does not read in time are dropped, as a real device would.
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        match self.master.write_all(bytes) {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{CaptureHeader, CaptureWriter};
+    use crate::event::DriverEvent;
+    use crate::transport::SerialTransport;
+    use crate::{check_device_health, get_device_info, run_driver};
+    use ydlidar_data::model_baud_rate;
+
+    #[test]
+    fn test_commands() {
+        let source = EmulatorSource::Generator {
+            next: Box::new(Vec::new),
+            interval: POLL_INTERVAL,
+        };
+        let emulator = Emulator::start(YdlidarModel::TMiniPro, source).unwrap();
+        let mut port =
+            SerialTransport::open(emulator.path(), model_baud_rate(YdlidarModel::TMiniPro))
+                .unwrap();
+        check_device_health(&mut port).unwrap();
+        let info = get_device_info(&mut port).unwrap();
+        assert_eq!(info.model_number, YdlidarModel::TMiniPro as u8);
+    }
+
+    #[test]
+    fn test_round_room() {
+        let round_room = EmulatorSource::round_room(YdlidarModel::X2, 1000, 50.).unwrap();
+        let emulator = Emulator::start(YdlidarModel::X2, round_room).unwrap();
+        let (thread, event_rx) =
+            run_driver(emulator.path(), YdlidarModel::X2, 200, 10, 0, 10).unwrap();
+
+        // The first lap may have started before the driver
+        let mut n_laps = 0;
+        while n_laps < 3 {
+            if let DriverEvent::Scan(scan) = event_rx.recv().unwrap() {
+                if n_laps > 0 {
+                    assert_eq!(scan.distances.len(), 481);
+                    assert!(scan.distances.iter().all(|&d| d == 1000));
+                    assert!(scan.checksum_correct);
+                }
+                n_laps += 1;
+            }
+        }
+
+        drop(thread);
+        emulator.stop().unwrap();
+    }
+
+    #[test]
+    fn test_round_room_invalid_frequency() {
+        for frequency in [0., -7., f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                EmulatorSource::round_room(YdlidarModel::X2, 1000, frequency),
+                Err(YDLidarError::InvalidConfiguration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_capture() {
+        let path =
+            std::env::temp_dir().join(format!("ydlidar-emulator-{}.ydlc", std::process::id()));
+        let header = CaptureHeader {
+            model: YdlidarModel::X2,
+            baud_rate: 115200,
+            device_info: None,
+        };
+        let mut writer = CaptureWriter::create(&path, &header).unwrap();
+        let encoder = PacketEncoder::new(YdlidarModel::X2);
+        for _ in 0..3 {
+            writer
+                .write_chunk(&encoder.scan_packet(true, 0., 0., &[500]))
+                .unwrap();
+            sleep_ms(10);
+        }
+        drop(writer);
+
+        let emulator =
+            Emulator::start(YdlidarModel::X2, EmulatorSource::Capture(path.clone())).unwrap();
+        let (thread, event_rx) =
+            run_driver(emulator.path(), YdlidarModel::X2, 200, 10, 0, 10).unwrap();
+        let mut n_laps = 0;
+        while n_laps < 3 {
+            if let DriverEvent::Scan(scan) = event_rx.recv().unwrap() {
+                if n_laps > 0 {
+                    assert_eq!(scan.distances, [500]);
+                }
+                n_laps += 1;
+            }
+        }
+
+        drop(thread);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
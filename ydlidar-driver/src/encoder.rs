@@ -0,0 +1,172 @@
+use crate::packet::{
+    encode_device_health, encode_device_info, encode_packet_type, encode_scan_packet,
+    encode_scan_started, SampleFormat,
+};
+use ydlidar_data::{DeviceInfo, YdlidarModel};
+
+/// Encoder of the bytes sent by the device, the inverse of [`ScanDecoder`](crate::ScanDecoder).
+///
+/// It builds scan packets with a valid checksum and the responses to the commands of
+/// the driver, for tests, simulators and emulators.
+///
+/// ```
+/// use ydlidar_data::YdlidarModel;
+/// use ydlidar_driver::{Decoded, PacketEncoder, ScanDecoder};
+///
+/// let encoder = PacketEncoder::new(YdlidarModel::X2);
+/// let mut decoder = ScanDecoder::new(YdlidarModel::X2);
+/// decoder.push(&encoder.scan_packet(false, 10., 20., &[1000, 1000, 1000]));
+/// match decoder.next() {
+///     Some(Decoded::Packet(packet)) => {
+///         assert!(packet.checksum_correct());
+///         assert_eq!(packet.n_samples(), 3);
+///         assert_eq!(packet.end_angle(), 20.);
+///     }
+///     _ => unreachable!(),
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct PacketEncoder {
+    model: YdlidarModel,
+    scan_frequency: Option<f64>,
+}
+
+impl PacketEncoder {
+    pub fn new(model: YdlidarModel) -> Self {
+        PacketEncoder {
+            model,
+            scan_frequency: None,
+        }
+    }
+
+    /// Scan frequency in Hz sent in the lap start packets, rounded to 0.1 Hz.
+    /// Not sent by default, nor above 12.7 Hz, the most the packets can hold.
+    pub fn scan_frequency(mut self, scan_frequency: f64) -> Self {
+        self.scan_frequency = Some(scan_frequency);
+        self
+    }
+
+    pub fn model(&self) -> YdlidarModel {
+        self.model
+    }
+
+    /// Scan packet holding `distances`, in mm, evenly spread from `start_angle` to
+    /// `end_angle`. Angles are in degrees as sent by the device, like
+    /// `Packet::start_angle`, and are rounded to 1/64 degree.
+    ///
+    /// # Panics
+    ///
+    /// If there are more than 255 distances, the most a packet can hold.
+    pub fn scan_packet(
+        &self,
+        lap_start: bool,
+        start_angle: f64,
+        end_angle: f64,
+        distances: &[u16],
+    ) -> Vec<u8> {
+        encode_scan_packet(
+            SampleFormat::of(self.model),
+            encode_packet_type(lap_start, self.scan_frequency),
+            start_angle,
+            end_angle,
+            distances,
+            None,
+        )
+    }
+
+    /// Same as [`scan_packet`](Self::scan_packet), with the intensity of each sample.
+    /// The intensities are dropped for models whose packets do not carry them.
+    ///
+    /// # Panics
+    ///
+    /// If `intensities` and `distances` differ in length.
+    pub fn scan_packet_with_intensities(
+        &self,
+        lap_start: bool,
+        start_angle: f64,
+        end_angle: f64,
+        distances: &[u16],
+        intensities: &[u8],
+    ) -> Vec<u8> {
+        encode_scan_packet(
+            SampleFormat::of(self.model),
+            encode_packet_type(lap_start, self.scan_frequency),
+            start_angle,
+            end_angle,
+            distances,
+            Some(intensities),
+        )
+    }
+
+    /// Response to the device information command.
+    pub fn device_info(&self, info: &DeviceInfo) -> Vec<u8> {
+        encode_device_info(info)
+    }
+
+    /// Response to the device health command. A status of 0 means healthy, any other
+    /// status is reported as `YDLidarError::DeviceHealthError` by the driver.
+    pub fn device_health(&self, status: u8, error_code: u16) -> Vec<u8> {
+        encode_device_health(status, error_code)
+    }
+
+    /// Response to the scan command, after which the device streams scan packets.
+    pub fn scan_started(&self) -> Vec<u8> {
+        encode_scan_started()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::{Decoded, ScanDecoder};
+    use crate::transport::MemoryTransport;
+    use crate::{check_device_health, get_device_info, YDLidarError};
+    use std::io::Write;
+
+    #[test]
+    fn test_decode_scan_packets() {
+        let encoder = PacketEncoder::new(YdlidarModel::TMiniPro);
+        let mut decoder = ScanDecoder::new(YdlidarModel::TMiniPro);
+        decoder.push(&encoder.scan_packet(true, 0., 0., &[500]));
+        decoder.push(&encoder.scan_packet(false, 1.5, 3., &[600, 0, 700]));
+        decoder.push(&encoder.scan_packet(true, 0., 0., &[500]));
+
+        assert!(matches!(decoder.next(), Some(Decoded::Scan(_))));
+        assert!(matches!(decoder.next(), Some(Decoded::Packet(p)) if p.is_lap_start()));
+        match decoder.next() {
+            Some(Decoded::Packet(packet)) => {
+                assert!(packet.checksum_correct());
+                assert!(!packet.is_lap_start());
+                assert_eq!(packet.start_angle(), 1.5);
+                assert_eq!(packet.end_angle(), 3.);
+            }
+            decoded => panic!("Expected a packet but decoded {:?}", decoded),
+        }
+        let scan = decoder.next_scan().unwrap();
+        assert_eq!(scan.distances, [500, 600, 700]);
+        assert!(scan.checksum_correct);
+    }
+
+    #[test]
+    fn test_responses() {
+        let encoder = PacketEncoder::new(YdlidarModel::X2);
+        let info = DeviceInfo {
+            model_number: YdlidarModel::X2 as u8,
+            firmware_major_version: 1,
+            firmware_minor_version: 4,
+            hardware_version: 2,
+            serial_number: [9; 16],
+        };
+        let (mut device, mut host) = MemoryTransport::pair();
+        device.write_all(&encoder.device_health(0, 0)).unwrap();
+        device.write_all(&encoder.device_info(&info)).unwrap();
+        device.write_all(&encoder.device_health(2, 0x0010)).unwrap();
+
+        check_device_health(&mut host).unwrap();
+        assert_eq!(get_device_info(&mut host).unwrap(), info);
+        assert!(matches!(
+            check_device_health(&mut host),
+            Err(YDLidarError::DeviceHealthError(2))
+        ));
+    }
+}
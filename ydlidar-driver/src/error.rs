@@ -12,6 +12,15 @@ pub enum YDLidarError {
     UnsupportedModel(u8),
     ChecksumMismatch(u16, u16),
     TimeoutError(),
+    InvalidConfiguration(String),
+    PortNotFound(String),
+    PortBusy(String),
+    PortPermissionDenied(String),
+    InvalidBaudRate(u32),
+    PortOpenError(String, serialport::Error),
+    NoDataReceived(u64),
+    ConnectionFailed(String, io::Error),
+    InvalidCapture(String),
     SerialError(serialport::Error),
     IoError(io::Error),
 }
@@ -28,6 +37,15 @@ impl Display for YDLidarError {
             YDLidarError::UnsupportedModel(model) => write!(f, "The model #{} is not supported", model),
             YDLidarError::ChecksumMismatch(expected, calculated) => write!(f, "Checksum mismatched. Calculated = {:04X}, expected = {:04X}.", calculated, expected),
             YDLidarError::TimeoutError() => write!(f, "Operation timed out"),
+            YDLidarError::InvalidConfiguration(reason) => write!(f, "Invalid driver configuration: {}.", reason),
+            YDLidarError::PortNotFound(port_name) => write!(f, "Serial port \"{}\" was not found.", port_name),
+            YDLidarError::PortBusy(port_name) => write!(f, "Serial port \"{}\" is already in use.", port_name),
+            YDLidarError::PortPermissionDenied(port_name) => write!(f, "Permission denied to open serial port \"{}\".", port_name),
+            YDLidarError::InvalidBaudRate(baud_rate) => write!(f, "Baud rate {} is not supported by the serial port.", baud_rate),
+            YDLidarError::PortOpenError(port_name, err) => write!(f, "Failed to open \"{}\". Error: {}", port_name, err),
+            YDLidarError::NoDataReceived(timeout) => write!(f, "No data received from the device within {} ms.", timeout),
+            YDLidarError::InvalidCapture(reason) => write!(f, "Invalid capture file: {}.", reason),
+            YDLidarError::ConnectionFailed(address, err) => write!(f, "Failed to connect to \"{}\". Error: {}", address, err),
             YDLidarError::IoError(err) => Display::fmt(&err, f),
             YDLidarError::SerialError(err) => Display::fmt(&err, f),
         }
@@ -0,0 +1,24 @@
+use crate::error::YDLidarError;
+use crate::watchdog::Stall;
+use ydlidar_data::Scan;
+
+/// Event sent by the driver threads to the consumer.
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum DriverEvent {
+    /// One lap of scan data.
+    Scan(Scan),
+    /// An error occurred in the driver threads. The driver keeps running.
+    Error(YDLidarError),
+    /// The watchdog detected that the lidar stopped streaming.
+    Stalled(Stall),
+    /// The driver is trying to reopen the lost device. Holds the attempt number, starting at 1.
+    Reconnecting(u32),
+    /// The device was reopened and the scan restarted.
+    Reconnected,
+    /// The device stopped responding, for example because the cable was pulled,
+    /// and could not be reopened.
+    Disconnected,
+    /// The driver threads stopped. This is the last event sent by the driver.
+    Stopped,
+}
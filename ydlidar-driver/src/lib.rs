@@ -1,31 +1,60 @@
-use std::sync::mpsc;
-
+#[cfg(all(feature = "tokio", unix))]
+pub mod async_driver;
+mod builder;
+mod capture;
 mod constants;
+mod decoder;
 mod driver_threads;
+#[cfg(unix)]
+mod emulator;
+mod encoder;
 mod error;
+mod event;
 mod flags;
 mod numeric;
+mod output;
 mod packet;
+mod reconnect;
+mod replay;
+mod rfc2217;
+mod ring_buffer;
 mod scan;
 mod serial;
+mod simulator;
+mod tcp;
+#[cfg(test)]
+mod test_util;
 mod time;
+mod transport;
+mod watchdog;
 
+pub use crate::builder::DriverBuilder;
+pub use crate::capture::{CaptureHeader, CaptureReader, CaptureWriter};
 use crate::constants::{
     HEADER_SIZE, LIDAR_ANS_LENGTH_DEVHEALTH, LIDAR_ANS_LENGTH_DEVINFO, LIDAR_ANS_TYPE_DEVHEALTH,
     LIDAR_ANS_TYPE_DEVINFO, LIDAR_CMD_GET_DEVICE_HEALTH, LIDAR_CMD_GET_DEVICE_INFO,
+    LIDAR_MAX_DISTANCE_VALUE,
 };
+pub use crate::decoder::{ChecksumPolicy, Decoded, Packet, ScanDecoder};
 pub use crate::driver_threads::DriverThreads;
-use crate::driver_threads::{parse_packets, read_device_signal};
+#[cfg(unix)]
+pub use crate::emulator::{Emulator, EmulatorSource};
+pub use crate::encoder::PacketEncoder;
 pub use crate::error::YDLidarError;
-use crate::packet::validate_response_header;
-use crate::serial::{read, send_command, start_scan, stop_scan_and_flush};
-use crate::time::sleep_ms;
-use constants::LIDAR_MAX_DISTANCE_VALUE;
-use crossbeam_channel::bounded;
-use serialport::SerialPort;
-use ydlidar_data::{model_baud_rate, DeviceInfo, Scan, YdlidarModel};
-
-pub fn check_device_health(port: &mut Box<dyn SerialPort>) -> Result<(), YDLidarError> {
+pub use crate::event::DriverEvent;
+pub use crate::output::{BackpressurePolicy, EventReceiver, Subscription};
+use crate::packet::{parse_device_health, parse_device_info, validate_response_header};
+pub use crate::reconnect::ReconnectPolicy;
+pub use crate::replay::{ReplayControl, ReplaySpeed, ReplayTransport};
+pub use crate::rfc2217::Rfc2217Transport;
+use crate::serial::{read, send_command};
+pub use crate::simulator::{Pose, Shape, SimulatedLap, Simulator};
+pub use crate::tcp::TcpTransport;
+pub use crate::transport::{IntoTransport, MemoryTransport, SerialTransport, Transport};
+pub use crate::watchdog::{Stall, Watchdog};
+use ydlidar_data::{model_baud_rate, DeviceInfo, YdlidarModel};
+
+pub fn check_device_health(port: &mut dyn Transport) -> Result<(), YDLidarError> {
     send_command(port, LIDAR_CMD_GET_DEVICE_HEALTH)?;
     let header = read(port, HEADER_SIZE)?;
     validate_response_header(
@@ -34,14 +63,10 @@ pub fn check_device_health(port: &mut Box<dyn SerialPort>) -> Result<(), YDLidar
         LIDAR_ANS_TYPE_DEVHEALTH,
     )?;
     let health = read(port, LIDAR_ANS_LENGTH_DEVHEALTH.into())?;
-
-    match health[0] {
-        0 => Ok(()),
-        _ => Err(YDLidarError::DeviceHealthError(health[0].into())),
-    }
+    parse_device_health(&health)
 }
 
-pub fn get_device_info(port: &mut Box<dyn SerialPort>) -> Result<DeviceInfo, YDLidarError> {
+pub fn get_device_info(port: &mut dyn Transport) -> Result<DeviceInfo, YDLidarError> {
     send_command(port, LIDAR_CMD_GET_DEVICE_INFO)?;
     let header = read(port, HEADER_SIZE)?;
     validate_response_header(
@@ -50,32 +75,23 @@ pub fn get_device_info(port: &mut Box<dyn SerialPort>) -> Result<DeviceInfo, YDL
         LIDAR_ANS_TYPE_DEVINFO,
     )?;
     let info = read(port, LIDAR_ANS_LENGTH_DEVINFO.into())?;
-    Ok(DeviceInfo {
-        model_number: info[0],
-        firmware_major_version: info[2],
-        firmware_minor_version: info[1],
-        hardware_version: info[3],
-        serial_number: info[4..20].try_into().unwrap(),
-    })
+    Ok(parse_device_info(&info))
 }
 
 /// Function to launch YDLiDAR.
-/// Uses default values of 1 and the corresponding LIDAR max rated distance for distance limits.
-/// See `run_driver_limits` for more information.
-/// # Arguments
-///
-/// * `port_name` - Serial port name such as `/dev/ttyUSB0`.
-/// * `model` - Model
+/// Keeps the distances from 1 to 8000 mm whatever the model, unlike `DriverBuilder`
+/// which defaults to the rated distance of the model.
+/// See `run_driver_limits` for the arguments.
 pub fn run_driver(
-    port_name: &str,
+    port: impl IntoTransport,
     model: YdlidarModel,
     scan_buffer: usize,
     out_buffer: usize,
     send_after: usize,
     sleep: u64,
-) -> Result<(DriverThreads, mpsc::Receiver<Scan>), YDLidarError> {
+) -> Result<(DriverThreads, EventReceiver), YDLidarError> {
     run_driver_limits(
-        port_name,
+        port,
         model,
         1,
         LIDAR_MAX_DISTANCE_VALUE,
@@ -87,14 +103,20 @@ pub fn run_driver(
 }
 
 /// Function to launch YDLiDAR with limit values.
+/// Prefer `DriverBuilder`, which names each of these values.
 /// # Arguments
 ///
-/// * `port_name` - Serial port name such as `/dev/ttyUSB0`.
+/// * `port` - Serial port name such as `/dev/ttyUSB0`, TCP endpoint such as `tcp://192.168.1.10:4001`, RFC 2217 endpoint such as `rfc2217://192.168.1.10:4001`, or any `Transport`.
 /// * `model` - Model
 /// * `min_distance` - Minimum distance to keep points (inclusive, e.g. 1 -> distances of 0 will be discarded)
 /// * `max_distance` - Maximum distance to keep points (inclusive, e.g. 5000 -> distances bigger than 5000 will be discarded)
+/// * `scan_buffer` - Number of raw reads buffered between the reader and the parser threads
+/// * `out_buffer` - Number of events buffered in the output channel
+/// * `send_after` - Sends a scan once it holds this many points (0 sends complete laps only)
+/// * `sleep` - Read timeout in milliseconds, the time a read blocks while the device sends nothing. See `DriverBuilder::read_timeout`
+#[allow(clippy::too_many_arguments)]
 pub fn run_driver_limits(
-    port_name: &str,
+    port: impl IntoTransport,
     model: YdlidarModel,
     min_distance: u16,
     max_distance: u16,
@@ -102,71 +124,29 @@ pub fn run_driver_limits(
     out_buffer: usize,
     send_after: usize,
     sleep: u64,
-) -> Result<(DriverThreads, mpsc::Receiver<Scan>), YDLidarError> {
-    let baud_rate = model_baud_rate(model);
-    let maybe_port = serialport::new(port_name, baud_rate)
-        .timeout(std::time::Duration::from_millis(10))
-        .open();
-
-    let mut port = match maybe_port {
-        Ok(port) => port,
-        Err(e) => {
-            eprintln!("Failed to open \"{}\". Error: {}", port_name, e);
-            std::process::exit(1);
-        }
-    };
-
-    if !cfg!(test) {
-        // In testing, disable flushing to receive dummy signals
-        stop_scan_and_flush(&mut port)?;
-        sleep_ms(10);
-        stop_scan_and_flush(&mut port)?;
-    }
-
-    // The X2 lidar does not support commands
-    // check_device_health(&mut port)?;
-    // get_device_info(&mut port)?;
-
-    let (reader_terminator_tx, reader_terminator_rx) = bounded(1);
-    let (parser_terminator_tx, parser_terminator_rx) = bounded(1);
-    let (scan_data_tx, scan_data_rx) = mpsc::sync_channel::<Vec<u8>>(scan_buffer);
-
-    start_scan(&mut port)?;
-
-    let reader_thread = Some(std::thread::spawn(move || {
-        read_device_signal(&mut port, scan_data_tx, reader_terminator_rx, sleep);
-    }));
-
-    let (scan_tx, scan_rx) = mpsc::sync_channel::<Scan>(out_buffer);
-    let receiver_thread = Some(std::thread::spawn(move || {
-        parse_packets(
-            scan_data_rx,
-            parser_terminator_rx,
-            scan_tx,
-            min_distance,
-            max_distance,
-            send_after,
-            sleep,
-        );
-    }));
-
-    let driver_threads = DriverThreads {
-        reader_thread,
-        receiver_thread,
-        reader_terminator_tx,
-        parser_terminator_tx,
-    };
-
-    Ok((driver_threads, scan_rx))
+) -> Result<(DriverThreads, EventReceiver), YDLidarError> {
+    let transport = port.into_transport(model_baud_rate(model))?;
+    DriverBuilder::new(&transport.name(), model)
+        .min_distance(min_distance)
+        .max_distance(max_distance)
+        .scan_buffer(scan_buffer)
+        .out_buffer(out_buffer)
+        .send_after(send_after)
+        .read_timeout(sleep)
+        .build_with_transport(transport)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serialport::TTYPort;
+    use crate::test_util::{recv_scan, start_driver, KeepInput, LAP_START};
+    use crate::time::sleep_ms;
+    use serialport::{SerialPort, TTYPort};
     use std::io::Write;
+    use ydlidar_data::InterferenceFlag;
 
     #[test]
+    #[allow(clippy::unused_io_amount)]
     fn test_check_device_health() {
         let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
         let mut slave_ptr = Box::new(slave) as Box<dyn SerialPort>;
@@ -188,6 +168,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::unused_io_amount)]
     fn test_get_device_info() {
         let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
         master
@@ -212,6 +193,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::unused_io_amount, clippy::unnecessary_cast)]
     fn test_run_driver_normal_data() {
         let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
 
@@ -229,8 +211,8 @@ mod tests {
 
         sleep_ms(10);
 
-        let name = slave.name().unwrap();
-        let (thread, scan_rx) = run_driver(&name, YdlidarModel::TMiniPro, 200, 10, 0, 100).unwrap();
+        let port = KeepInput::open(slave.name().unwrap(), YdlidarModel::TMiniPro);
+        let (thread, event_rx) = run_driver(port, YdlidarModel::TMiniPro, 200, 10, 0, 100).unwrap();
 
         let packet = [
             // beginning of a lap
@@ -260,10 +242,10 @@ mod tests {
         ];
         master.write(&packet).unwrap();
 
-        let scan = scan_rx.recv().unwrap();
+        let scan = recv_scan(&event_rx);
         assert_eq!(scan.angles_radian.len(), 0);
 
-        let scan = scan_rx.recv().unwrap();
+        let scan = recv_scan(&event_rx);
         assert_eq!(scan.angles_radian.len(), 17);
 
         let expected = vec![
@@ -305,6 +287,37 @@ mod tests {
     }
 
     #[test]
+    fn test_run_driver_interference_flags() {
+        let (mut device, host) = MemoryTransport::pair();
+        let (thread, event_rx) = start_driver(host, &mut device, 10);
+
+        let packet = [
+            0xAA, 0x55, 0xC7, 0x01, 0x01, 0x15, 0x01, 0x15, 0x1B, 0x56, // lap start
+            0x14, 0x62, 0x02, // Specular Reflection
+            0xAA, 0x55, 0xB0, 0x04, 0x81, 0x16, 0x01, 0x2D, 0x80, 0x68, // packet header
+            0xDD, 0x76, 0x03, // Specular Reflection
+            0xB3, 0x7B, 0x03, // Ambient Light
+            0x8E, 0x8A, 0x03, // Specular Reflection
+            0x9B, 0xE6, 0x01, // Specular Reflection
+        ];
+        device.write_all(&packet).unwrap();
+        device.write_all(&LAP_START).unwrap();
+
+        let scan = recv_scan(&event_rx);
+        assert_eq!(scan.distances.len(), 0);
+        let scan = recv_scan(&event_rx);
+        assert!(scan.checksum_correct);
+        let mut expected = vec![InterferenceFlag::SpecularReflection; 5];
+        expected[2] = InterferenceFlag::AmbientLight;
+        assert_eq!(scan.interference_flags, Some(expected));
+        assert_eq!(scan.interference_counts.specular_reflection, 4);
+        assert_eq!(scan.interference_counts.ambient_light, 1);
+
+        drop(thread);
+    }
+
+    #[test]
+    #[allow(clippy::unused_io_amount)]
     fn test_run_driver_mod_at_360() {
         let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
 
@@ -322,8 +335,8 @@ mod tests {
 
         sleep_ms(10);
 
-        let name = slave.name().unwrap();
-        let (thread, scan_rx) = run_driver(&name, YdlidarModel::TMiniPro, 200, 10, 0, 100).unwrap();
+        let port = KeepInput::open(slave.name().unwrap(), YdlidarModel::TMiniPro);
+        let (thread, event_rx) = run_driver(port, YdlidarModel::TMiniPro, 200, 10, 0, 100).unwrap();
 
         let packet = [
             // lap data
@@ -338,7 +351,7 @@ mod tests {
 
         sleep_ms(10);
 
-        let scan = scan_rx.recv().unwrap();
+        let scan = recv_scan(&event_rx);
         assert_eq!(scan.angles_radian.len(), 16);
 
         let expected = vec![
@@ -361,6 +374,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::unused_io_amount)]
     fn test_run_driver_checksum() {
         let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
 
@@ -378,8 +392,8 @@ mod tests {
 
         sleep_ms(10);
 
-        let name = slave.name().unwrap();
-        let (thread, scan_rx) = run_driver(&name, YdlidarModel::TMiniPro, 200, 10, 0, 100).unwrap();
+        let port = KeepInput::open(slave.name().unwrap(), YdlidarModel::TMiniPro);
+        let (thread, event_rx) = run_driver(port, YdlidarModel::TMiniPro, 200, 10, 0, 100).unwrap();
 
         let packet = [
             // lap data
@@ -392,9 +406,94 @@ mod tests {
         ];
         master.write(&packet).unwrap();
 
-        let scan = scan_rx.recv().unwrap();
+        let scan = recv_scan(&event_rx);
         assert!(!scan.checksum_correct);
 
         drop(thread);
     }
+
+    #[test]
+    fn test_run_driver_disconnected() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let (thread, event_rx) = start_driver(slave.name().unwrap(), &mut master, 10);
+
+        drop(master);
+        drop(slave);
+
+        let mut events = Vec::new();
+        while let Ok(event) = event_rx.recv() {
+            events.push(event);
+        }
+        assert!(matches!(
+            events.as_slice(),
+            [
+                DriverEvent::Error(_),
+                DriverEvent::Disconnected,
+                DriverEvent::Stopped
+            ]
+        ));
+
+        drop(thread);
+    }
+
+    #[test]
+    fn test_run_driver_latency_independent_of_read_timeout() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let (thread, event_rx) = start_driver(slave.name().unwrap(), &mut master, 1000);
+
+        let start = std::time::Instant::now();
+        master.write_all(&LAP_START).unwrap();
+
+        recv_scan(&event_rx);
+        assert!(start.elapsed() < std::time::Duration::from_millis(500));
+
+        drop(thread);
+    }
+
+    #[test]
+    fn test_run_driver_recycle() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let (thread, event_rx) = start_driver(slave.name().unwrap(), &mut master, 10);
+
+        master.write_all(&LAP_START).unwrap();
+        let mut scan = recv_scan(&event_rx);
+        scan.distances.reserve(1000);
+        thread.recycle(scan);
+
+        // The scan started by this packet was allocated before the recycled one was available
+        master.write_all(&LAP_START).unwrap();
+        let scan = recv_scan(&event_rx);
+        assert!(scan.distances.capacity() < 1000);
+
+        master.write_all(&LAP_START).unwrap();
+        let scan = recv_scan(&event_rx);
+        assert!(scan.distances.capacity() >= 1000);
+        assert_eq!(scan.distances.len(), 1);
+        assert!(scan.checksum_correct);
+
+        drop(thread);
+    }
+
+    #[test]
+    fn test_run_driver_memory_transport() {
+        let (mut device, host) = MemoryTransport::pair();
+        let (thread, event_rx) = start_driver(host, &mut device, 10);
+
+        device.write_all(&LAP_START).unwrap();
+        let scan = recv_scan(&event_rx);
+        assert_eq!(scan.distances.len(), 0);
+
+        drop(device);
+        let events: Vec<DriverEvent> = event_rx.iter().collect();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                DriverEvent::Error(_),
+                DriverEvent::Disconnected,
+                DriverEvent::Stopped
+            ]
+        ));
+
+        drop(thread);
+    }
 }
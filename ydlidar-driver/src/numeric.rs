@@ -1,3 +1,5 @@
+use ydlidar_data::YdlidarModel;
+
 pub(crate) fn to_u16(a: u8, b: u8) -> u16 {
     ((a as u16) << 8) + (b as u16)
 }
@@ -22,11 +24,17 @@ pub(crate) fn to_string(data: &[u8]) -> String {
         .join(" ")
 }
 
-pub(crate) fn correct_angle(angle: f64, distance: u16) -> f64 {
-    // Correcting the angle for the X2 lidar
-    if distance == 0 {
-        angle
-    } else {
-        angle - (21.8 * ((155.3 - distance as f64) / (155.3 * distance as f64))).atan()
+/// Corrects the angle in degrees of a sample of a triangulation lidar, the X2, for
+/// which the laser and the camera are not on the rotation axis.
+pub(crate) fn correct_angle(model: YdlidarModel, angle: f64, distance: u16) -> f64 {
+    match model {
+        YdlidarModel::X2 if distance != 0 => {
+            let distance = distance as f64;
+            angle
+                - (21.8 * (155.3 - distance) / (155.3 * distance))
+                    .atan()
+                    .to_degrees()
+        }
+        _ => angle,
     }
 }
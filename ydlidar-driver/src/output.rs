@@ -0,0 +1,549 @@
+use crate::event::DriverEvent;
+use crossbeam_channel::{
+    bounded, never, select, unbounded, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError,
+};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use ydlidar_data::Scan;
+
+/// Receiving end of the driver events. It dereferences to a crossbeam `Receiver`,
+/// e.g. to wait on several channels with `select!`.
+///
+/// The driver stops once every clone of the receiver is dropped and no subscriber is
+/// left. With `BackpressurePolicy::Block`, a `Receiver` cloned out of it also keeps the
+/// driver running, and the parser thread waits while its queue is full. With the other
+/// policies, such a `Receiver` does not keep the driver running.
+#[derive(Clone, Debug)]
+pub struct EventReceiver {
+    rx: Receiver<DriverEvent>,
+    /// Tells the driver that the consumer is still there, as the driver holds a
+    /// receiver of its own to discard old laps.
+    _alive: Arc<()>,
+}
+
+impl EventReceiver {
+    pub(crate) fn new(rx: Receiver<DriverEvent>, alive: Arc<()>) -> Self {
+        EventReceiver { rx, _alive: alive }
+    }
+}
+
+impl Deref for EventReceiver {
+    type Target = Receiver<DriverEvent>;
+
+    fn deref(&self) -> &Receiver<DriverEvent> {
+        &self.rx
+    }
+}
+
+/// What the driver does when the consumer does not keep up with the output channel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// The parser thread waits for the consumer. Once the buffers between the threads
+    /// are full, the data read from the device is lost.
+    #[default]
+    Block,
+    /// The oldest queued lap is discarded to make room for the new one. The other
+    /// events, such as errors and disconnections, are never discarded.
+    DropOldest,
+    /// Only the newest lap is kept, whatever the size of the output buffer.
+    /// Suited to control loops that only care about the freshest lap.
+    LatestOnly,
+}
+
+/// Item of an output channel, counted as a dropped lap when discarded.
+pub(crate) trait Lap {
+    fn is_lap(&self) -> bool;
+}
+
+impl Lap for DriverEvent {
+    fn is_lap(&self) -> bool {
+        matches!(self, DriverEvent::Scan(_))
+    }
+}
+
+impl Lap for Arc<Scan> {
+    fn is_lap(&self) -> bool {
+        true
+    }
+}
+
+pub(crate) enum SendError {
+    /// The receiver was dropped.
+    Disconnected,
+    /// The parser thread was asked to terminate while waiting for the consumer.
+    Terminated,
+}
+
+/// Sends items to one consumer according to the backpressure policy.
+pub(crate) struct EventSender<T> {
+    tx: Sender<T>,
+    /// Set when the policy drops laps.
+    discard: Option<Discard<T>>,
+    dropped_laps: Arc<AtomicU64>,
+}
+
+/// State used to discard the oldest laps of a channel.
+struct Discard<T> {
+    /// Number of laps the channel holds.
+    capacity: usize,
+    /// Receiver used to take the queued items out.
+    rx: Receiver<T>,
+    /// Queued items being put back, kept to reuse its storage.
+    queued: Mutex<Vec<T>>,
+    /// Alive as long as the consumer is. The receiver above keeps the channel
+    /// connected, so the disconnection of the consumer is not reported by the channel.
+    consumer: Weak<()>,
+}
+
+impl<T: Lap> EventSender<T> {
+    /// Creates an output channel holding up to `depth` laps. The returned token must
+    /// be kept along with the receiver: the consumer is considered gone once it is
+    /// dropped.
+    pub(crate) fn new(
+        policy: BackpressurePolicy,
+        depth: usize,
+        dropped_laps: Arc<AtomicU64>,
+    ) -> (Self, Receiver<T>, Arc<()>) {
+        let alive = Arc::new(());
+        let (tx, rx, discard) = match policy {
+            BackpressurePolicy::Block => {
+                let (tx, rx) = bounded(depth.max(1));
+                (tx, rx, None)
+            }
+            BackpressurePolicy::DropOldest | BackpressurePolicy::LatestOnly => {
+                // The other events come on top of the laps, so the channel is not bounded
+                let (tx, rx) = unbounded();
+                let capacity = match policy {
+                    BackpressurePolicy::LatestOnly => 1,
+                    _ => depth.max(1),
+                };
+                let discard = Discard {
+                    capacity,
+                    rx: rx.clone(),
+                    queued: Mutex::new(Vec::new()),
+                    consumer: Arc::downgrade(&alive),
+                };
+                (tx, rx, Some(discard))
+            }
+        };
+        let sender = EventSender {
+            tx,
+            discard,
+            dropped_laps,
+        };
+        (sender, rx, alive)
+    }
+
+    /// Sends an item to the consumer, waiting for room with `BackpressurePolicy::Block`.
+    pub(crate) fn send(&self, item: T, terminator_rx: &Receiver<bool>) -> Result<(), SendError> {
+        match &self.discard {
+            Some(discard) => self.send_dropping_oldest(discard, item),
+            None => select! {
+                send(self.tx, item) -> result => result.map_err(|_| SendError::Disconnected),
+                recv(terminator_rx) -> _ => Err(SendError::Terminated),
+            },
+        }
+    }
+
+    /// Sends an item without waiting for the consumer.
+    pub(crate) fn try_send(&self, item: T) {
+        match &self.discard {
+            Some(discard) => {
+                let _ = self.send_dropping_oldest(discard, item);
+            }
+            None => {
+                let _ = self.tx.try_send(item);
+            }
+        }
+    }
+
+    fn send_dropping_oldest(&self, discard: &Discard<T>, item: T) -> Result<(), SendError> {
+        if discard.consumer.strong_count() == 0 {
+            return Err(SendError::Disconnected);
+        }
+        // Fewer queued items than the capacity means fewer laps as well
+        if item.is_lap() && discard.rx.len() >= discard.capacity {
+            // The queue is taken out and put back in order without its oldest laps.
+            // The consumer can only take items out meanwhile, so the order is kept.
+            let mut queued = discard.queued.lock().unwrap();
+            queued.extend(discard.rx.try_iter());
+            let n_laps = queued.iter().filter(|queued| queued.is_lap()).count();
+            let mut n_dropped = (n_laps + 1).saturating_sub(discard.capacity);
+            self.dropped_laps
+                .fetch_add(n_dropped as u64, Ordering::Relaxed);
+            for queued in queued.drain(..) {
+                if n_dropped > 0 && queued.is_lap() {
+                    n_dropped -= 1;
+                } else {
+                    let _ = self.tx.send(queued);
+                }
+            }
+        }
+        let _ = self.tx.send(item);
+        Ok(())
+    }
+}
+
+/// Subscribers registered on a driver.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    next_id: u64,
+    senders: Vec<(u64, Arc<EventSender<Arc<Scan>>>)>,
+    /// Set once the parser thread stopped, no lap will be sent anymore.
+    closed: bool,
+}
+
+/// Registers a new subscriber.
+pub(crate) fn subscribe(
+    subscribers: &Arc<Mutex<Subscribers>>,
+    depth: usize,
+    policy: BackpressurePolicy,
+) -> Subscription {
+    let dropped_laps = Arc::new(AtomicU64::new(0));
+    let (sender, rx, alive) = EventSender::new(policy, depth, dropped_laps.clone());
+    let mut guard = subscribers.lock().unwrap();
+    let id = guard.next_id;
+    guard.next_id += 1;
+    // Once closed, the sender is dropped right away so the subscription is disconnected
+    if !guard.closed {
+        guard.senders.push((id, Arc::new(sender)));
+    }
+    Subscription {
+        id,
+        rx,
+        _alive: alive,
+        dropped_laps,
+        subscribers: subscribers.clone(),
+    }
+}
+
+/// Independent stream of the laps decoded by a driver.
+/// Each lap is copied once and shared between every subscriber.
+/// The subscriber leaves the driver when the subscription is dropped.
+pub struct Subscription {
+    id: u64,
+    rx: Receiver<Arc<Scan>>,
+    _alive: Arc<()>,
+    dropped_laps: Arc<AtomicU64>,
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl Subscription {
+    /// Channel of the laps, for instance to wait on several channels with `select!`.
+    /// The channel is disconnected once the driver stops.
+    pub fn receiver(&self) -> &Receiver<Arc<Scan>> {
+        &self.rx
+    }
+
+    /// Waits for the next lap.
+    pub fn recv(&self) -> Result<Arc<Scan>, RecvError> {
+        self.rx.recv()
+    }
+
+    /// Returns the next lap if one is available.
+    pub fn try_recv(&self) -> Result<Arc<Scan>, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    /// Waits for the next lap for at most `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Arc<Scan>, RecvTimeoutError> {
+        self.rx.recv_timeout(timeout)
+    }
+
+    /// Number of laps discarded because this subscriber did not keep up.
+    pub fn dropped_laps(&self) -> u64 {
+        self.dropped_laps.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Disconnects the channel first, so that a parser thread blocked on sending
+        // to this subscriber moves on
+        drop(std::mem::replace(&mut self.rx, never()));
+        let mut guard = self.subscribers.lock().unwrap();
+        guard.senders.retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// Sends the driver events to the consumer and the laps to every subscriber.
+pub(crate) struct Broadcaster {
+    event_tx: EventSender<DriverEvent>,
+    event_rx_connected: bool,
+    subscribers: Arc<Mutex<Subscribers>>,
+    /// Subscribers a lap is being sent to, kept to reuse its storage.
+    targets: Vec<(u64, Arc<EventSender<Arc<Scan>>>)>,
+    /// Subscribers found disconnected while sending a lap.
+    disconnected: Vec<u64>,
+}
+
+impl Broadcaster {
+    pub(crate) fn new(
+        event_tx: EventSender<DriverEvent>,
+        subscribers: Arc<Mutex<Subscribers>>,
+    ) -> Self {
+        Broadcaster {
+            event_tx,
+            event_rx_connected: true,
+            subscribers,
+            targets: Vec::new(),
+            disconnected: Vec::new(),
+        }
+    }
+
+    /// Sends an event. Fails once the parser thread should stop, that is when it was
+    /// asked to terminate or when nobody is left to receive the events.
+    pub(crate) fn send(
+        &mut self,
+        event: DriverEvent,
+        terminator_rx: &Receiver<bool>,
+    ) -> Result<(), ()> {
+        if let DriverEvent::Scan(scan) = &event {
+            // The list is not locked while sending, so that subscribers can join or
+            // leave while a blocking subscriber is waited for
+            let guard = self.subscribers.lock().unwrap();
+            self.targets.extend(guard.senders.iter().cloned());
+            drop(guard);
+            if !self.targets.is_empty() {
+                let shared = Arc::new(scan.clone());
+                for (id, sender) in self.targets.drain(..) {
+                    match sender.send(shared.clone(), terminator_rx) {
+                        Ok(()) => {}
+                        Err(SendError::Disconnected) => self.disconnected.push(id),
+                        Err(SendError::Terminated) => return Err(()),
+                    }
+                }
+            }
+        }
+        let mut guard = self.subscribers.lock().unwrap();
+        if !self.disconnected.is_empty() {
+            let disconnected = &self.disconnected;
+            guard.senders.retain(|(id, _)| !disconnected.contains(id));
+            self.disconnected.clear();
+        }
+        let has_subscribers = !guard.senders.is_empty();
+        drop(guard);
+
+        if self.event_rx_connected {
+            match self.event_tx.send(event, terminator_rx) {
+                Ok(()) => {}
+                Err(SendError::Disconnected) => self.event_rx_connected = false,
+                Err(SendError::Terminated) => return Err(()),
+            }
+        }
+        if self.event_rx_connected || has_subscribers {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Sends an event to the consumer without waiting.
+    pub(crate) fn try_send(&self, event: DriverEvent) {
+        self.event_tx.try_send(event);
+    }
+}
+
+impl Drop for Broadcaster {
+    fn drop(&mut self) {
+        // Disconnects every subscription
+        let mut guard = self.subscribers.lock().unwrap();
+        guard.senders.clear();
+        guard.closed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::YdLidarScan;
+
+    fn scan(n_points: usize) -> DriverEvent {
+        DriverEvent::Scan(Scan {
+            angles_radian: vec![0.; n_points],
+            distances: vec![0; n_points],
+            ..Scan::new()
+        })
+    }
+
+    fn n_points(event: DriverEvent) -> usize {
+        match event {
+            DriverEvent::Scan(scan) => scan.distances.len(),
+            event => panic!("Expected a scan but received {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let dropped_laps = Arc::new(AtomicU64::new(0));
+        let (sender, rx, _alive) =
+            EventSender::new(BackpressurePolicy::DropOldest, 2, dropped_laps.clone());
+        let (_terminator_tx, terminator_rx) = bounded(1);
+        for n in 1..=4 {
+            assert!(sender.send(scan(n), &terminator_rx).is_ok());
+        }
+        assert_eq!(dropped_laps.load(Ordering::Relaxed), 2);
+        assert_eq!(n_points(rx.try_recv().unwrap()), 3);
+        assert_eq!(n_points(rx.try_recv().unwrap()), 4);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_latest_only() {
+        let dropped_laps = Arc::new(AtomicU64::new(0));
+        let (sender, rx, _alive) =
+            EventSender::new(BackpressurePolicy::LatestOnly, 10, dropped_laps.clone());
+        let (_terminator_tx, terminator_rx) = bounded(1);
+        for n in 1..=3 {
+            assert!(sender.send(scan(n), &terminator_rx).is_ok());
+        }
+        sender.try_send(DriverEvent::Stopped);
+        assert_eq!(dropped_laps.load(Ordering::Relaxed), 2);
+        assert_eq!(n_points(rx.try_recv().unwrap()), 3);
+        assert!(matches!(rx.try_recv(), Ok(DriverEvent::Stopped)));
+    }
+
+    #[test]
+    fn test_control_events_kept() {
+        let dropped_laps = Arc::new(AtomicU64::new(0));
+        let (sender, rx, _alive) =
+            EventSender::new(BackpressurePolicy::LatestOnly, 1, dropped_laps.clone());
+        let (_terminator_tx, terminator_rx) = bounded(1);
+        for n in 1..=5 {
+            assert!(sender.send(scan(n), &terminator_rx).is_ok());
+        }
+        assert!(sender
+            .send(DriverEvent::Disconnected, &terminator_rx)
+            .is_ok());
+        for n in 6..=8 {
+            assert!(sender.send(scan(n), &terminator_rx).is_ok());
+        }
+        sender.try_send(DriverEvent::Stopped);
+
+        // Only laps are dropped, and the order of the events is kept
+        assert!(matches!(rx.try_recv(), Ok(DriverEvent::Disconnected)));
+        assert_eq!(n_points(rx.try_recv().unwrap()), 8);
+        assert!(matches!(rx.try_recv(), Ok(DriverEvent::Stopped)));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(dropped_laps.load(Ordering::Relaxed), 7);
+    }
+
+    #[test]
+    fn test_block() {
+        let dropped_laps = Arc::new(AtomicU64::new(0));
+        let (sender, rx, _alive) =
+            EventSender::new(BackpressurePolicy::Block, 1, dropped_laps.clone());
+        let (terminator_tx, terminator_rx) = bounded(1);
+        assert!(sender.send(scan(1), &terminator_rx).is_ok());
+        // The channel is full, only termination unblocks the sender
+        terminator_tx.send(true).unwrap();
+        assert!(matches!(
+            sender.send(scan(2), &terminator_rx),
+            Err(SendError::Terminated)
+        ));
+        assert_eq!(n_points(rx.try_recv().unwrap()), 1);
+
+        drop(rx);
+        assert!(matches!(
+            sender.send(scan(3), &terminator_rx),
+            Err(SendError::Disconnected)
+        ));
+        assert_eq!(dropped_laps.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+        let (event_tx, event_rx, _alive) =
+            EventSender::new(BackpressurePolicy::Block, 10, Arc::new(AtomicU64::new(0)));
+        let mut broadcaster = Broadcaster::new(event_tx, subscribers.clone());
+        let (_terminator_tx, terminator_rx) = bounded(1);
+
+        let first = subscribe(&subscribers, 10, BackpressurePolicy::Block);
+        let second = subscribe(&subscribers, 1, BackpressurePolicy::LatestOnly);
+        assert!(broadcaster.send(scan(1), &terminator_rx).is_ok());
+        assert!(broadcaster
+            .send(DriverEvent::Reconnected, &terminator_rx)
+            .is_ok());
+        assert!(broadcaster.send(scan(2), &terminator_rx).is_ok());
+
+        // Subscribers share the laps and receive nothing else
+        assert_eq!(first.try_recv().unwrap().distances.len(), 1);
+        let lap = first.try_recv().unwrap();
+        assert!(Arc::ptr_eq(&lap, &second.try_recv().unwrap()));
+        assert!(first.try_recv().is_err());
+        assert_eq!(second.dropped_laps(), 1);
+        assert_eq!(event_rx.len(), 3);
+
+        // Subscribers leave when dropped
+        drop(first);
+        assert_eq!(subscribers.lock().unwrap().senders.len(), 1);
+
+        // The driver keeps running while a subscriber is left
+        drop(event_rx);
+        assert!(broadcaster.send(scan(3), &terminator_rx).is_ok());
+        drop(second);
+        assert!(broadcaster.send(scan(4), &terminator_rx).is_err());
+
+        // Subscriptions are disconnected once the driver stops
+        let subscription = subscribe(&subscribers, 1, BackpressurePolicy::Block);
+        drop(broadcaster);
+        assert!(subscription.recv().is_err());
+        let subscription = subscribe(&subscribers, 1, BackpressurePolicy::Block);
+        assert!(subscription.recv().is_err());
+    }
+
+    #[test]
+    fn test_join_while_waiting() {
+        let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+        let (event_tx, _event_rx, _alive) = EventSender::new(
+            BackpressurePolicy::DropOldest,
+            10,
+            Arc::new(AtomicU64::new(0)),
+        );
+        let mut broadcaster = Broadcaster::new(event_tx, subscribers.clone());
+        let slow = subscribe(&subscribers, 1, BackpressurePolicy::Block);
+        let (_terminator_tx, terminator_rx) = bounded(1);
+        let sending = std::thread::spawn(move || {
+            broadcaster.send(scan(1), &terminator_rx).unwrap();
+            // Waits for the slow subscriber
+            broadcaster.send(scan(2), &terminator_rx)
+        });
+        while slow.receiver().is_empty() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+
+        // Subscribers join and leave while the parser waits
+        let other = subscribe(&subscribers, 1, BackpressurePolicy::Block);
+        drop(other);
+        assert_eq!(slow.recv().unwrap().distances.len(), 1);
+        assert!(sending.join().unwrap().is_ok());
+        assert_eq!(slow.recv().unwrap().distances.len(), 2);
+    }
+
+    #[test]
+    fn test_consumer_dropped() {
+        for policy in [
+            BackpressurePolicy::DropOldest,
+            BackpressurePolicy::LatestOnly,
+        ] {
+            let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+            let (event_tx, event_rx, alive) =
+                EventSender::new(policy, 2, Arc::new(AtomicU64::new(0)));
+            let event_rx = EventReceiver::new(event_rx, alive);
+            let mut broadcaster = Broadcaster::new(event_tx, subscribers);
+            let (_terminator_tx, terminator_rx) = bounded(1);
+            assert!(broadcaster.send(scan(1), &terminator_rx).is_ok());
+
+            // The driver stops once the consumer and all its clones are gone
+            let clone = event_rx.clone();
+            drop(event_rx);
+            assert!(broadcaster.send(scan(2), &terminator_rx).is_ok());
+            drop(clone);
+            assert!(broadcaster.send(scan(3), &terminator_rx).is_err());
+        }
+    }
+}
@@ -1,20 +1,13 @@
 #[allow(dead_code)] // Temporary fix until feature flags to select ydlidar
-use crate::constants::{HEADER_SIZE, LIDAR_CMD_SYNC_BYTE, PACKET_HEADER_SIZE};
+use crate::constants::{
+    HEADER_SIZE, LIDAR_ANS_LENGTH_DEVHEALTH, LIDAR_ANS_LENGTH_DEVINFO, LIDAR_ANS_TYPE_DEVHEALTH,
+    LIDAR_ANS_TYPE_DEVINFO, LIDAR_ANS_TYPE_MEASUREMENT, LIDAR_CMD_SYNC_BYTE, PACKET_HEADER_SIZE,
+};
 use crate::error::YDLidarError;
-use crate::numeric::{to_string, to_u16};
-use std::collections::VecDeque;
-
-fn get_packet_size(buffer: &VecDeque<u8>, start_index: usize) -> Result<usize, ()> {
-    let index = start_index + 3;
-    if index >= buffer.len() {
-        return Err(());
-    }
-    let n_scan_samples = match buffer.get(index) {
-        Some(n) => n,
-        None => return Err(()),
-    };
-    Ok(10 + (*n_scan_samples as usize) * 3)
-}
+use crate::flags::to_flag;
+use crate::numeric::{calc_distance, to_angle, to_string, to_u16};
+use crate::ring_buffer::RingBuffer;
+use ydlidar_data::{DeviceInfo, InterferenceFlag, YdlidarModel};
 
 pub(crate) fn validate_response_header(
     header: &[u8],
@@ -27,15 +20,12 @@ pub(crate) fn validate_response_header(
     if header[0..2] != [LIDAR_CMD_SYNC_BYTE, 0x5A] {
         return Err(YDLidarError::InvalidMagicNumber(to_string(&header[0..2])));
     }
-    match maybe_response_length {
-        None => (),
-        Some(len) => {
-            if header[2] != len {
-                return Err(YDLidarError::InvalidResponseLength(
-                    len.into(),
-                    header[2].into(),
-                ));
-            }
+    if let Some(len) = maybe_response_length {
+        if header[2] != len {
+            return Err(YDLidarError::InvalidResponseLength(
+                len.into(),
+                header[2].into(),
+            ));
         }
     }
     if header[6] != type_code {
@@ -58,17 +48,91 @@ fn validate_packet_response(header: &[u8]) -> Result<(), YDLidarError> {
     Ok(())
 }
 
-fn calc_checksum(packet: &[u8]) -> u16 {
-    let n_scan = packet[3] as usize;
+/// Layout of the samples of a scan packet, which depends on the model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SampleFormat {
+    /// Two bytes per sample: the distance in 1/4 mm, little endian. Sent by the X2.
+    Distance,
+    /// Three bytes per sample: the intensity, then the flags on the 2 lowest bits and
+    /// the distance in mm on the 14 others, little endian. Sent by the T-mini Pro.
+    Intensity,
+}
+
+impl SampleFormat {
+    pub(crate) fn of(model: YdlidarModel) -> Self {
+        match model {
+            YdlidarModel::X2 => SampleFormat::Distance,
+            _ => SampleFormat::Intensity,
+        }
+    }
+
+    pub(crate) fn sample_size(self) -> usize {
+        match self {
+            SampleFormat::Distance => 2,
+            SampleFormat::Intensity => 3,
+        }
+    }
+
+    pub(crate) fn packet_size(self, n_samples: usize) -> usize {
+        PACKET_HEADER_SIZE + n_samples * self.sample_size()
+    }
+
+    /// Bytes of the sample `idx` of the packet.
+    pub(crate) fn sample(self, packet: &[u8], idx: usize) -> &[u8] {
+        let start = PACKET_HEADER_SIZE + idx * self.sample_size();
+        &packet[start..start + self.sample_size()]
+    }
+
+    /// Distance of a sample in mm.
+    pub(crate) fn distance(self, sample: &[u8]) -> u16 {
+        match self {
+            SampleFormat::Distance => to_u16(sample[1], sample[0]) / 4,
+            SampleFormat::Intensity => calc_distance(sample[1], sample[2]),
+        }
+    }
+
+    pub(crate) fn has_intensity(self) -> bool {
+        self == SampleFormat::Intensity
+    }
+
+    /// Intensity of a sample, for the formats that carry it.
+    pub(crate) fn intensity(self, sample: &[u8]) -> Option<u8> {
+        self.has_intensity().then_some(sample[0])
+    }
+
+    /// Interference flag of a sample, for the formats that carry it.
+    pub(crate) fn interference_flag(self, sample: &[u8]) -> Option<InterferenceFlag> {
+        self.has_intensity().then(|| to_flag(sample[1] & 0x03))
+    }
+
+    /// Appends a sample at `distance` mm. The intensity is dropped by the formats
+    /// that do not carry it.
+    fn push_sample(self, packet: &mut Vec<u8>, distance: u16, intensity: u8) {
+        match self {
+            SampleFormat::Distance => packet.extend((distance.min(0x3FFF) * 4).to_le_bytes()),
+            SampleFormat::Intensity => packet.extend([
+                intensity,
+                ((distance & 0x3F) << 2) as u8,
+                (distance >> 6) as u8,
+            ]),
+        }
+    }
+}
+
+fn calc_checksum(packet: &[u8], format: SampleFormat) -> u16 {
+    let n_scan = n_scan_samples(packet);
 
     let mut checksum: u16 = to_u16(packet[1], packet[0]);
     checksum ^= to_u16(packet[5], packet[4]);
     for i in 0..n_scan {
-        let s0 = packet[10 + 3 * i];
-        let s1 = packet[10 + 3 * i + 1];
-        let s2 = packet[10 + 3 * i + 2];
-        checksum ^= to_u16(0x00, s0);
-        checksum ^= to_u16(s2, s1);
+        let sample = format.sample(packet, i);
+        match format {
+            SampleFormat::Distance => checksum ^= to_u16(sample[1], sample[0]),
+            SampleFormat::Intensity => {
+                checksum ^= to_u16(0x00, sample[0]);
+                checksum ^= to_u16(sample[2], sample[1]);
+            }
+        }
     }
     checksum ^= to_u16(packet[3], packet[2]);
     checksum ^= to_u16(packet[7], packet[6]);
@@ -83,33 +147,113 @@ pub(crate) fn is_beginning_of_cycle(packet: &[u8]) -> bool {
     packet[2] & 0x01 == 1
 }
 
-fn find_start_index(buffer: &VecDeque<u8>) -> Result<usize, ()> {
-    if buffer.is_empty() {
-        return Err(());
+/// Scan frequency in Hz sent in bits 7:1 of the CT byte of lap start packets, in
+/// units of 0.1 Hz. `None` if the packet does not carry it.
+pub(crate) fn lap_scan_frequency(packet: &[u8]) -> Option<f64> {
+    let units = packet[2] >> 1;
+    (is_beginning_of_cycle(packet) && units != 0).then(|| units as f64 / 10.)
+}
+
+/// CT byte of a packet. A scan frequency that does not fit in the 7 bits is left out.
+pub(crate) fn encode_packet_type(lap_start: bool, scan_frequency: Option<f64>) -> u8 {
+    if !lap_start {
+        return 0x00;
     }
-    for i in 0..(buffer.len() - 1) {
-        let e0 = match buffer.get(i) {
-            Some(e) => e,
-            None => continue,
-        };
-        let e1 = match buffer.get(i + 1) {
-            Some(e) => e,
-            None => continue,
-        };
-        if is_packet_header(*e0, *e1) {
-            return Ok(i);
+    let units = scan_frequency.map_or(0., |frequency| (frequency * 10.).round());
+    let units = if (0. ..=127.).contains(&units) {
+        units as u8
+    } else {
+        0
+    };
+    units << 1 | 0x01
+}
+
+/// Result of the search for the next packet at the front of a buffer.
+#[derive(Debug, PartialEq)]
+pub(crate) enum PacketSearch {
+    /// A packet of `size` bytes starts at `start`. The bytes before it are garbage.
+    Found { start: usize, size: usize },
+    /// More bytes are needed. The first `garbage` bytes cannot be part of a packet.
+    Incomplete { garbage: usize },
+}
+
+enum Candidate {
+    Valid(usize),
+    Invalid,
+    Incomplete,
+}
+
+/// Looks for the first packet of the buffer and copies it to the start of `packet`.
+///
+/// A `0xAA 0x55` pair can also appear in sample data, so each candidate header is
+/// checked before being trusted: the sample count must not be 0, the angles must carry
+/// their check bit and be below 360 degrees, and the packet must either have a valid
+/// checksum or be followed by the next header. A candidate failing these checks is
+/// skipped, and the search resumes from the next byte.
+pub(crate) fn find_packet(
+    buffer: &RingBuffer,
+    format: SampleFormat,
+    packet: &mut [u8],
+) -> PacketSearch {
+    let mut start = 0;
+    while start + 1 < buffer.len() {
+        if !is_packet_header(buffer.get(start).unwrap(), buffer.get(start + 1).unwrap()) {
+            start += 1;
+            continue;
         }
+        match check_candidate(buffer, format, start, packet) {
+            Candidate::Valid(size) => return PacketSearch::Found { start, size },
+            Candidate::Incomplete => return PacketSearch::Incomplete { garbage: start },
+            Candidate::Invalid => start += 1,
+        }
+    }
+    // The last byte may be the first one of a header
+    let garbage = match buffer.get(start) {
+        Some(0xAA) => start,
+        _ => buffer.len(),
+    };
+    PacketSearch::Incomplete { garbage }
+}
+
+fn check_candidate(
+    buffer: &RingBuffer,
+    format: SampleFormat,
+    start: usize,
+    packet: &mut [u8],
+) -> Candidate {
+    if buffer.len() < start + PACKET_HEADER_SIZE {
+        return Candidate::Incomplete;
+    }
+    buffer.copy_from(start, &mut packet[..PACKET_HEADER_SIZE]);
+    if !is_plausible_header(&packet[..PACKET_HEADER_SIZE]) {
+        return Candidate::Invalid;
+    }
+    let size = format.packet_size(n_scan_samples(packet));
+    if buffer.len() < start + size {
+        return Candidate::Incomplete;
+    }
+    buffer.copy_from(start, &mut packet[..size]);
+    if err_if_checksum_mismatched(&packet[..size], format).is_ok() {
+        return Candidate::Valid(size);
+    }
+    // A corrupted packet is kept if the next one starts right after it
+    match (buffer.get(start + size), buffer.get(start + size + 1)) {
+        (Some(e0), Some(e1)) if is_packet_header(e0, e1) => Candidate::Valid(size),
+        (Some(_), Some(_)) => Candidate::Invalid,
+        _ => Candidate::Incomplete,
     }
-    Err(())
 }
 
-pub(crate) fn sendable_packet_range(buffer: &VecDeque<u8>) -> Result<(usize, usize), ()> {
-    let start_index = find_start_index(buffer)?;
-    let end_index = get_packet_size(buffer, start_index)?;
-    Ok((start_index, end_index))
+fn is_plausible_header(header: &[u8]) -> bool {
+    let is_valid_angle = |lo: u8, hi: u8| lo & 0x01 == 1 && to_angle(lo, hi) < 360.;
+    header[3] > 0 && is_valid_angle(header[4], header[5]) && is_valid_angle(header[6], header[7])
 }
-pub(crate) fn err_if_checksum_mismatched(packet: &[u8]) -> Result<(), YDLidarError> {
-    let calculated = calc_checksum(packet);
+
+pub(crate) fn err_if_checksum_mismatched(
+    packet: &[u8],
+    format: SampleFormat,
+) -> Result<(), YDLidarError> {
+    let calculated = calc_checksum(packet, format);
     let expected = to_u16(packet[9], packet[8]);
     match calculated != expected {
         true => Err(YDLidarError::ChecksumMismatch(expected, calculated)),
@@ -117,8 +261,116 @@ pub(crate) fn err_if_checksum_mismatched(packet: &[u8]) -> Result<(), YDLidarErr
     }
 }
 
-pub(crate) fn scan_index(idx: usize) -> usize {
-    10 + idx * 3
+pub(crate) fn parse_device_health(health: &[u8]) -> Result<(), YDLidarError> {
+    match health[0] {
+        0 => Ok(()),
+        _ => Err(YDLidarError::DeviceHealthError(health[0].into())),
+    }
+}
+
+pub(crate) fn parse_device_info(info: &[u8]) -> DeviceInfo {
+    DeviceInfo {
+        model_number: info[0],
+        firmware_major_version: info[2],
+        firmware_minor_version: info[1],
+        hardware_version: info[3],
+        serial_number: info[4..20].try_into().unwrap(),
+    }
+}
+
+/// Payload of the device information response, the inverse of `parse_device_info`.
+pub(crate) fn device_info_bytes(info: &DeviceInfo) -> [u8; LIDAR_ANS_LENGTH_DEVINFO as usize] {
+    let mut bytes = [0u8; LIDAR_ANS_LENGTH_DEVINFO as usize];
+    bytes[0] = info.model_number;
+    bytes[1] = info.firmware_minor_version;
+    bytes[2] = info.firmware_major_version;
+    bytes[3] = info.hardware_version;
+    bytes[4..20].copy_from_slice(&info.serial_number);
+    bytes
+}
+
+fn response_header(length: u8, type_code: u8) -> Vec<u8> {
+    vec![
+        LIDAR_CMD_SYNC_BYTE,
+        0x5A,
+        length,
+        0x00,
+        0x00,
+        0x00,
+        type_code,
+    ]
+}
+
+/// Response to `LIDAR_CMD_GET_DEVICE_INFO`.
+pub(crate) fn encode_device_info(info: &DeviceInfo) -> Vec<u8> {
+    let mut response = response_header(LIDAR_ANS_LENGTH_DEVINFO, LIDAR_ANS_TYPE_DEVINFO);
+    response.extend(device_info_bytes(info));
+    response
+}
+
+/// Response to `LIDAR_CMD_GET_DEVICE_HEALTH`. A status of 0 means healthy.
+pub(crate) fn encode_device_health(status: u8, error_code: u16) -> Vec<u8> {
+    let mut response = response_header(LIDAR_ANS_LENGTH_DEVHEALTH, LIDAR_ANS_TYPE_DEVHEALTH);
+    response.push(status);
+    response.extend(error_code.to_le_bytes());
+    response
+}
+
+/// Response to `LIDAR_CMD_SCAN`, followed by the scan packets.
+pub(crate) fn encode_scan_started() -> Vec<u8> {
+    let mut response = response_header(0x05, LIDAR_ANS_TYPE_MEASUREMENT);
+    // Continuous response mode
+    response[5] = 0x40;
+    response
+}
+
+/// Encodes an angle in degrees as sent by the device, the inverse of `to_angle`.
+pub(crate) fn encode_angle(angle: f64) -> [u8; 2] {
+    let angle = ((angle * 64.).round() as i64).rem_euclid(360 * 64) as u16;
+    // The lowest bit is a check bit, always set
+    ((angle << 1) | 1).to_le_bytes()
+}
+
+/// Builds a scan packet as sent by the device, with a valid checksum.
+/// Angles are in degrees, distances in mm. Intensities default to 0.
+pub(crate) fn encode_scan_packet(
+    format: SampleFormat,
+    packet_type: u8,
+    start_angle: f64,
+    end_angle: f64,
+    distances: &[u16],
+    intensities: Option<&[u8]>,
+) -> Vec<u8> {
+    assert!(distances.len() <= u8::MAX as usize);
+    if let Some(intensities) = intensities {
+        assert_eq!(intensities.len(), distances.len());
+    }
+    let mut packet = vec![0xAA, 0x55, packet_type, distances.len() as u8];
+    packet.extend(encode_angle(start_angle));
+    packet.extend(encode_angle(end_angle));
+    packet.extend([0x00, 0x00]);
+    for (i, &distance) in distances.iter().enumerate() {
+        let intensity = intensities.map_or(0, |intensities| intensities[i]);
+        format.push_sample(&mut packet, distance, intensity);
+    }
+    let checksum = calc_checksum(&packet, format);
+    packet[8..10].copy_from_slice(&checksum.to_le_bytes());
+    packet
+}
+
+/// Angle in degrees of the sample `idx` of a packet of `n` samples, before the X2
+/// correction. The samples are evenly spread between the start and end angles.
+pub(crate) fn sample_angle(start_angle: f64, end_angle: f64, n: usize, idx: usize) -> f64 {
+    if idx == 0 {
+        return start_angle;
+    }
+    if idx == n - 1 {
+        return end_angle;
+    }
+    let angle_shift = if start_angle > end_angle { 0f64 } else { 360. };
+    let angle_diff = end_angle - start_angle + angle_shift;
+    let angle_rate: f64 = angle_diff / ((n - 1) as f64);
+    (start_angle + (idx as f64) * angle_rate) % 360.
 }
 
 pub(crate) fn n_scan_samples(packet: &[u8]) -> usize {
@@ -133,7 +385,7 @@ mod tests {
     fn test_validate_response_header() {
         assert!(matches!(
             validate_response_header(
-                &vec![0xA5, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x04],
+                &[0xA5, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x04],
                 Some(0x14),
                 0x04
             ),
@@ -142,7 +394,7 @@ mod tests {
 
         assert!(matches!(
             validate_response_header(
-                &vec![0xA5, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x04, 0x09],
+                &[0xA5, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x04, 0x09],
                 Some(0x14),
                 0x04
             ),
@@ -151,7 +403,7 @@ mod tests {
 
         assert!(matches!(
             validate_response_header(
-                &vec![0xA6, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x04],
+                &[0xA6, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x04],
                 Some(0x14),
                 0x04
             ),
@@ -160,7 +412,7 @@ mod tests {
 
         assert!(matches!(
             validate_response_header(
-                &vec![0xA5, 0x2A, 0x14, 0x00, 0x00, 0x00, 0x04],
+                &[0xA5, 0x2A, 0x14, 0x00, 0x00, 0x00, 0x04],
                 Some(0x14),
                 0x04
             ),
@@ -169,7 +421,7 @@ mod tests {
 
         assert!(matches!(
             validate_response_header(
-                &vec![0xA5, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x04],
+                &[0xA5, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x04],
                 Some(0x12),
                 0x04
             ),
@@ -178,7 +430,7 @@ mod tests {
 
         assert!(matches!(
             validate_response_header(
-                &vec![0xA5, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x08],
+                &[0xA5, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x08],
                 Some(0x14),
                 0x04
             ),
@@ -200,7 +452,7 @@ mod tests {
             0x84, 0x9A, 0x0A, 0x7E, 0xCE, 0x0A, 0x4E, 0x7E, 0x04, 0x51, 0x6E, 0x03, 0x66, 0xA6,
             0x02,
         ];
-        let checksum = calc_checksum(&packet);
+        let checksum = calc_checksum(&packet, SampleFormat::Intensity);
         let expected = to_u16(packet[9], packet[8]);
         assert_eq!(checksum, expected);
 
@@ -216,8 +468,63 @@ mod tests {
             0xC1, 0x0A, 0x0A, 0xBF, 0x1A, 0x0A, 0xB9, 0x1E, 0x0A, 0xAA, 0x22, 0x0A, 0x9E, 0x2A,
             0x0A, 0xCB, 0x7A, 0x15,
         ];
-        let checksum = calc_checksum(&packet);
+        let checksum = calc_checksum(&packet, SampleFormat::Intensity);
         let expected = to_u16(packet[9], packet[8]);
         assert_eq!(checksum, expected);
     }
+
+    #[test]
+    fn test_encode_scan_packet() {
+        let format = SampleFormat::Intensity;
+        let packet = encode_scan_packet(format, 0x00, 42., 90., &[150, 0, 8000], None);
+        assert_eq!(packet.len(), PACKET_HEADER_SIZE + 9);
+        assert!(!is_beginning_of_cycle(&packet));
+        assert_eq!(to_angle(packet[4], packet[5]), 42.);
+        assert_eq!(to_angle(packet[6], packet[7]), 90.);
+        assert_eq!(calc_distance(packet[11], packet[12]), 150);
+        assert_eq!(calc_distance(packet[17], packet[18]), 8000);
+        assert!(err_if_checksum_mismatched(&packet, format).is_ok());
+
+        let packet_type = encode_packet_type(true, Some(6.96));
+        let packet = encode_scan_packet(format, packet_type, 0., 0., &[100], None);
+        assert!(is_beginning_of_cycle(&packet));
+        assert_eq!(lap_scan_frequency(&packet), Some(7.));
+        assert!(err_if_checksum_mismatched(&packet, format).is_ok());
+        assert_eq!(encode_packet_type(true, Some(50.)), 0x01);
+        assert_eq!(encode_packet_type(false, Some(7.)), 0x00);
+
+        let format = SampleFormat::Distance;
+        let packet = encode_scan_packet(format, 0x00, 42., 90., &[150, 0, 8000], None);
+        assert_eq!(packet.len(), PACKET_HEADER_SIZE + 6);
+        assert_eq!(format.distance(format.sample(&packet, 0)), 150);
+        assert_eq!(format.distance(format.sample(&packet, 2)), 8000);
+        assert!(err_if_checksum_mismatched(&packet, format).is_ok());
+    }
+
+    #[test]
+    fn test_encode_responses() {
+        let info = DeviceInfo {
+            model_number: 150,
+            firmware_major_version: 1,
+            firmware_minor_version: 2,
+            hardware_version: 3,
+            serial_number: [4; 16],
+        };
+        let response = encode_device_info(&info);
+        assert!(validate_response_header(
+            &response[..HEADER_SIZE],
+            Some(LIDAR_ANS_LENGTH_DEVINFO),
+            LIDAR_ANS_TYPE_DEVINFO
+        )
+        .is_ok());
+        assert_eq!(parse_device_info(&response[HEADER_SIZE..]), info);
+
+        let response = encode_device_health(0, 0);
+        assert!(parse_device_health(&response[HEADER_SIZE..]).is_ok());
+        let response = encode_device_health(2, 0x0102);
+        assert!(matches!(
+            parse_device_health(&response[HEADER_SIZE..]),
+            Err(YDLidarError::DeviceHealthError(2))
+        ));
+    }
 }
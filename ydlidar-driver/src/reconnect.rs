@@ -0,0 +1,171 @@
+use crate::driver_threads::ReaderEvent;
+use crate::serial::reset_scan;
+use crate::transport::Transport;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+/// Policy to reopen the transport after the device disappeared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Maximum number of attempts before giving up. `None` retries forever.
+    pub max_attempts: Option<u32>,
+    /// Delay in milliseconds before the first attempt. Must be greater than 0.
+    pub initial_backoff: u64,
+    /// Upper bound of the delay in milliseconds, which doubles after each failed attempt.
+    pub max_backoff: u64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: None,
+            initial_backoff: 100,
+            max_backoff: 5000,
+        }
+    }
+}
+
+pub(crate) enum ReconnectResult {
+    Reconnected,
+    GaveUp,
+    Terminated,
+}
+
+pub(crate) struct Reconnector {
+    pub(crate) policy: ReconnectPolicy,
+}
+
+impl Reconnector {
+    /// Reopens the transport with backoff and restarts the scan.
+    /// Every attempt is reported to the parser thread.
+    pub(crate) fn reconnect(
+        &self,
+        port: &mut dyn Transport,
+        scan_data_tx: &Sender<ReaderEvent>,
+        reader_terminator_rx: &Receiver<bool>,
+    ) -> ReconnectResult {
+        let mut backoff = self.policy.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            if self.policy.max_attempts.is_some_and(|max| attempt >= max) {
+                return ReconnectResult::GaveUp;
+            }
+            attempt += 1;
+
+            match reader_terminator_rx.recv_timeout(Duration::from_millis(backoff)) {
+                Ok(true) | Err(RecvTimeoutError::Disconnected) => {
+                    return ReconnectResult::Terminated
+                }
+                Ok(false) | Err(RecvTimeoutError::Timeout) => {}
+            }
+            backoff = backoff.saturating_mul(2).min(self.policy.max_backoff);
+
+            if scan_data_tx
+                .send(ReaderEvent::Reconnecting(attempt))
+                .is_err()
+            {
+                return ReconnectResult::Terminated;
+            }
+            match port.reconnect().and_then(|_| reset_scan(port)) {
+                Ok(()) => {
+                    let _ = scan_data_tx.send(ReaderEvent::Reconnected);
+                    return ReconnectResult::Reconnected;
+                }
+                Err(e) => {
+                    let _ = scan_data_tx.send(ReaderEvent::Error(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DriverBuilder;
+    use crate::event::DriverEvent;
+    use crate::test_util::{answer_scan, KeepInput, LAP_START};
+    use serialport::{SerialPort, TTYPort};
+    use std::io::Write;
+    use ydlidar_data::YdlidarModel;
+
+    fn link_to(link: &std::path::Path, port: &TTYPort) {
+        let tmp = link.with_extension("tmp");
+        std::os::unix::fs::symlink(port.name().unwrap(), &tmp).unwrap();
+        std::fs::rename(&tmp, link).unwrap();
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let link = std::env::temp_dir().join(format!("ydlidar-reconnect-{}", std::process::id()));
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        link_to(&link, &slave);
+        answer_scan(&mut master);
+
+        let policy = ReconnectPolicy {
+            max_attempts: Some(100),
+            initial_backoff: 10,
+            max_backoff: 10,
+        };
+        let name = link.to_str().unwrap();
+        let (thread, event_rx) = DriverBuilder::new(name, YdlidarModel::TMiniPro)
+            .read_timeout(10)
+            .reconnect(policy)
+            .build_with_transport(Box::new(KeepInput::open(name, YdlidarModel::TMiniPro)))
+            .unwrap();
+
+        let (mut new_master, new_slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        link_to(&link, &new_slave);
+        drop(master);
+        drop(slave);
+
+        assert!(matches!(event_rx.recv().unwrap(), DriverEvent::Error(_)));
+        assert!(matches!(
+            event_rx.recv().unwrap(),
+            DriverEvent::Reconnecting(1)
+        ));
+        assert!(matches!(event_rx.recv().unwrap(), DriverEvent::Reconnected));
+
+        new_master.write_all(&LAP_START).unwrap();
+        assert!(matches!(event_rx.recv().unwrap(), DriverEvent::Scan(_)));
+
+        drop(thread);
+        std::fs::remove_file(&link).unwrap();
+    }
+
+    #[test]
+    fn test_reconnect_gives_up() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        answer_scan(&mut master);
+
+        let policy = ReconnectPolicy {
+            max_attempts: Some(2),
+            initial_backoff: 1,
+            max_backoff: 1,
+        };
+        let name = slave.name().unwrap();
+        let (thread, event_rx) = DriverBuilder::new(&name, YdlidarModel::X2)
+            .read_timeout(10)
+            .reconnect(policy)
+            .build_with_transport(Box::new(KeepInput::open(&name, YdlidarModel::X2)))
+            .unwrap();
+        drop(master);
+        drop(slave);
+
+        let events: Vec<DriverEvent> = event_rx.iter().collect();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                DriverEvent::Error(_),
+                DriverEvent::Reconnecting(1),
+                DriverEvent::Error(_),
+                DriverEvent::Reconnecting(2),
+                DriverEvent::Error(_),
+                DriverEvent::Disconnected,
+                DriverEvent::Stopped
+            ]
+        ));
+
+        drop(thread);
+    }
+}
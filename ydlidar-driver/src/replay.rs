@@ -0,0 +1,403 @@
+use crate::capture::{CaptureHeader, CaptureReader};
+use crate::error::YDLidarError;
+use crate::transport::Transport;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Pace at which a capture is played back.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReplaySpeed {
+    /// Chunks are delivered with the delays they were recorded with.
+    #[default]
+    RealTime,
+    /// Delays are divided by the factor, e.g. 2.0 plays twice as fast.
+    Scaled(f64),
+    /// Chunks are delivered as fast as they are read.
+    Unpaced,
+}
+
+#[derive(Default)]
+struct ControlState {
+    paused: bool,
+    seek: Option<Duration>,
+}
+
+#[derive(Default)]
+struct ReplayShared {
+    state: Mutex<ControlState>,
+    changed: Condvar,
+}
+
+/// Handle to pause, resume and seek a `ReplayTransport` while the driver plays it.
+#[derive(Clone)]
+pub struct ReplayControl {
+    shared: Arc<ReplayShared>,
+}
+
+impl ReplayControl {
+    /// Stops delivering bytes until `resume` is called.
+    pub fn pause(&self) {
+        self.update(|state| state.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.update(|state| state.paused = false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.state.lock().unwrap().paused
+    }
+
+    /// Continues the playback from the first chunk recorded at or after `time`, counted
+    /// from the start of the capture.
+    pub fn seek(&self, time: Duration) {
+        self.update(|state| state.seek = Some(time));
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ControlState)) {
+        f(&mut self.shared.state.lock().unwrap());
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Transport playing a capture file back, so that the driver decodes recorded data as
+/// if it came from the device.
+///
+/// The end of the capture disconnects the transport, unless it loops.
+///
+/// ```no_run
+/// use ydlidar_driver::{DriverBuilder, ReplaySpeed, ReplayTransport};
+///
+/// let replay = ReplayTransport::open("lidar.ydlc".as_ref())
+///     .unwrap()
+///     .speed(ReplaySpeed::Scaled(2.0))
+///     .unwrap();
+/// let control = replay.control();
+/// let model = replay.header().model;
+/// let (driver_threads, event_rx) = DriverBuilder::new("replay", model)
+///     .build_with_transport(Box::new(replay))
+///     .unwrap();
+/// control.pause();
+/// ```
+pub struct ReplayTransport {
+    name: String,
+    reader: CaptureReader,
+    speed: ReplaySpeed,
+    looping: bool,
+    shared: Arc<ReplayShared>,
+    timeout: Duration,
+    /// Chunk being delivered, its timestamp and the number of bytes already delivered.
+    chunk: Vec<u8>,
+    chunk_time: Option<Duration>,
+    offset: usize,
+    /// Wall clock time matching a capture timestamp. Reset when the playback jumps.
+    anchor: Option<(Instant, Duration)>,
+}
+
+impl ReplayTransport {
+    /// Opens a capture file written by `CaptureWriter`.
+    pub fn open(path: &Path) -> Result<Self, YDLidarError> {
+        Ok(ReplayTransport {
+            name: format!("replay:{}", path.display()),
+            reader: CaptureReader::open(path)?,
+            speed: ReplaySpeed::RealTime,
+            looping: false,
+            shared: Arc::new(ReplayShared::default()),
+            timeout: Duration::from_millis(10),
+            chunk: Vec::new(),
+            chunk_time: None,
+            offset: 0,
+            anchor: None,
+        })
+    }
+
+    /// Pace of the playback. Defaults to `ReplaySpeed::RealTime`.
+    /// Fails if a scaled speed is not positive.
+    pub fn speed(mut self, speed: ReplaySpeed) -> Result<Self, YDLidarError> {
+        if let ReplaySpeed::Scaled(factor) = speed {
+            if factor.is_nan() || factor <= 0.0 {
+                return Err(YDLidarError::InvalidConfiguration(format!(
+                    "replay speed ({factor}) must be greater than 0"
+                )));
+            }
+        }
+        self.speed = speed;
+        Ok(self)
+    }
+
+    /// Starts again from the beginning at the end of the capture.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Model, baud rate and device information of the capture.
+    pub fn header(&self) -> &CaptureHeader {
+        self.reader.header()
+    }
+
+    pub fn control(&self) -> ReplayControl {
+        ReplayControl {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Applies pending seek requests. Returns true while paused.
+    fn apply_controls(&mut self) -> Result<bool, YDLidarError> {
+        let (paused, seek) = {
+            let mut state = self.shared.state.lock().unwrap();
+            (state.paused, state.seek.take())
+        };
+        if let Some(time) = seek {
+            self.seek(time)?;
+        }
+        if paused {
+            // Resuming must not rush through the time spent paused
+            self.anchor = None;
+        }
+        Ok(paused)
+    }
+
+    fn seek(&mut self, time: Duration) -> Result<(), YDLidarError> {
+        self.reader.rewind()?;
+        self.anchor = None;
+        loop {
+            self.offset = 0;
+            self.chunk_time = self.reader.next_chunk(&mut self.chunk)?;
+            match self.chunk_time {
+                Some(chunk_time) if chunk_time < time => {}
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Makes sure a chunk with bytes left to deliver is loaded.
+    /// Returns false at the end of the capture.
+    fn load(&mut self) -> Result<bool, YDLidarError> {
+        let mut rewound = false;
+        while self.chunk_time.is_none() || self.offset >= self.chunk.len() {
+            self.offset = 0;
+            self.chunk_time = self.reader.next_chunk(&mut self.chunk)?;
+            if self.chunk_time.is_none() {
+                // An empty capture must not loop forever
+                if !self.looping || rewound {
+                    return Ok(false);
+                }
+                self.reader.rewind()?;
+                self.anchor = None;
+                rewound = true;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Time left before the loaded chunk is due.
+    fn time_to_due(&mut self) -> Duration {
+        let chunk_time = self.chunk_time.unwrap_or_default();
+        let factor = match self.speed {
+            ReplaySpeed::RealTime => 1.0,
+            ReplaySpeed::Scaled(factor) => factor,
+            ReplaySpeed::Unpaced => return Duration::ZERO,
+        };
+        let now = Instant::now();
+        let (start, base) = *self.anchor.get_or_insert((now, chunk_time));
+        let due = start + chunk_time.saturating_sub(base).div_f64(factor);
+        due.saturating_duration_since(now)
+    }
+
+    /// Sleeps until `timeout` elapses or the controls change.
+    fn wait(&self, timeout: Duration) {
+        let state = self.shared.state.lock().unwrap();
+        let _ = self.shared.changed.wait_timeout(state, timeout).unwrap();
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Returns `Ok(0)` at the end of the capture, unless it loops.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.apply_controls().map_err(io::Error::other)? {
+            self.wait(self.timeout);
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        if !self.load().map_err(io::Error::other)? {
+            return Ok(0);
+        }
+        let time_to_due = self.time_to_due();
+        if time_to_due > Duration::ZERO {
+            self.wait(time_to_due.min(self.timeout));
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let n_read = buf.len().min(self.chunk.len() - self.offset);
+        buf[..n_read].copy_from_slice(&self.chunk[self.offset..self.offset + n_read]);
+        self.offset += n_read;
+        Ok(n_read)
+    }
+
+    /// Commands sent to a recording go nowhere.
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        Ok(data.len())
+    }
+
+    fn bytes_available(&mut self) -> Result<usize, YDLidarError> {
+        if self.apply_controls()? || !self.load()? || self.time_to_due() > Duration::ZERO {
+            return Ok(0);
+        }
+        Ok(self.chunk.len() - self.offset)
+    }
+
+    /// Nothing is buffered ahead of the capture, so there is nothing to discard.
+    fn flush(&mut self) -> Result<(), YDLidarError> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), YDLidarError> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureWriter;
+    use crate::event::DriverEvent;
+    use crate::run_driver;
+    use crate::test_util::LAP_START;
+    use crate::time::sleep_ms;
+    use std::path::PathBuf;
+    use ydlidar_data::YdlidarModel;
+
+    /// Records `n_chunks` lap starts, `interval` milliseconds apart.
+    fn record(name: &str, n_chunks: usize, interval: u64) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("ydlidar-{}-{}.ydlc", name, std::process::id()));
+        let header = CaptureHeader {
+            model: YdlidarModel::TMiniPro,
+            baud_rate: 230400,
+            device_info: None,
+        };
+        let mut writer = CaptureWriter::create(&path, &header).unwrap();
+        for i in 0..n_chunks {
+            if i > 0 {
+                sleep_ms(interval);
+            }
+            writer.write_chunk(&LAP_START).unwrap();
+        }
+        path
+    }
+
+    fn read_all(replay: &mut ReplayTransport, n: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; n];
+        let mut n_read = 0;
+        while n_read < n {
+            match Transport::read(replay, &mut bytes[n_read..]) {
+                Ok(0) => panic!("End of the capture"),
+                Ok(n) => n_read += n,
+                Err(e) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_replay_driver() {
+        let path = record("replay", 3, 50);
+        let replay = ReplayTransport::open(&path).unwrap();
+        let start = Instant::now();
+        let (thread, event_rx) =
+            run_driver(replay, YdlidarModel::TMiniPro, 200, 10, 0, 10).unwrap();
+
+        let mut n_scans = 0;
+        let events: Vec<DriverEvent> = event_rx.iter().collect();
+        for event in &events {
+            if matches!(event, DriverEvent::Scan(_)) {
+                n_scans += 1;
+            }
+        }
+        assert_eq!(n_scans, 3);
+        // The original pacing is kept
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert!(matches!(
+            events.as_slice(),
+            [
+                ..,
+                DriverEvent::Error(_),
+                DriverEvent::Disconnected,
+                DriverEvent::Stopped
+            ]
+        ));
+
+        drop(thread);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_speed() {
+        let path = record("replay-speed", 3, 100);
+        let mut replay = ReplayTransport::open(&path)
+            .unwrap()
+            .speed(ReplaySpeed::Scaled(10.0))
+            .unwrap();
+        let start = Instant::now();
+        read_all(&mut replay, 3 * LAP_START.len());
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(15));
+        assert!(elapsed < Duration::from_millis(150));
+
+        let mut replay = ReplayTransport::open(&path)
+            .unwrap()
+            .speed(ReplaySpeed::Unpaced)
+            .unwrap();
+        let start = Instant::now();
+        read_all(&mut replay, 3 * LAP_START.len());
+        assert!(start.elapsed() < Duration::from_millis(15));
+        assert_eq!(Transport::read(&mut replay, &mut [0u8; 1]).unwrap(), 0);
+
+        for factor in [0.0, -1.0, f64::NAN] {
+            let replay = ReplayTransport::open(&path).unwrap();
+            assert!(matches!(
+                replay.speed(ReplaySpeed::Scaled(factor)),
+                Err(YDLidarError::InvalidConfiguration(_))
+            ));
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_controls() {
+        let path = record("replay-controls", 2, 10);
+        let mut replay = ReplayTransport::open(&path)
+            .unwrap()
+            .speed(ReplaySpeed::Unpaced)
+            .unwrap()
+            .looping(true);
+        let control = replay.control();
+
+        control.pause();
+        assert!(control.is_paused());
+        assert_eq!(replay.bytes_available().unwrap(), 0);
+        let err = Transport::read(&mut replay, &mut [0u8; 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        control.resume();
+
+        // Loops over both chunks
+        let bytes = read_all(&mut replay, 3 * LAP_START.len());
+        assert_eq!(bytes, [LAP_START, LAP_START, LAP_START].concat());
+
+        // Skips the first chunk
+        control.seek(Duration::from_millis(5));
+        assert_eq!(replay.bytes_available().unwrap(), LAP_START.len());
+        read_all(&mut replay, LAP_START.len());
+        control.seek(Duration::ZERO);
+        read_all(&mut replay, 2 * LAP_START.len());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,558 @@
+use crate::constants::{CONNECT_TIMEOUT, READ_CHUNK_SIZE};
+use crate::error::YDLidarError;
+use crate::tcp::{connect, non_zero};
+use crate::transport::Transport;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+// Telnet commands (RFC 854)
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+// Telnet options
+const BINARY: u8 = 0;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+// Com port subcommands (RFC 2217). The server answers with the same code plus 100.
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const PURGE_DATA: u8 = 12;
+const SERVER_OFFSET: u8 = 100;
+
+const PARITY_NONE: u8 = 1;
+const STOPSIZE_1: u8 = 1;
+const DTR_ON: u8 = 8;
+const DTR_OFF: u8 = 9;
+const RTS_ON: u8 = 11;
+const RTS_OFF: u8 = 12;
+const PURGE_RECEIVE: u8 = 1;
+const PURGE_BOTH: u8 = 3;
+
+enum TelnetState {
+    Data,
+    Iac,
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+enum TelnetEvent {
+    Negotiation(u8, u8),
+    Subnegotiation(Vec<u8>),
+}
+
+/// Splits a telnet stream into data bytes and commands.
+struct TelnetDecoder {
+    state: TelnetState,
+    subnegotiation: Vec<u8>,
+}
+
+impl TelnetDecoder {
+    fn new() -> Self {
+        TelnetDecoder {
+            state: TelnetState::Data,
+            subnegotiation: Vec::new(),
+        }
+    }
+
+    fn decode(&mut self, bytes: &[u8], data: &mut VecDeque<u8>, events: &mut Vec<TelnetEvent>) {
+        for &byte in bytes {
+            self.state = match (&self.state, byte) {
+                (TelnetState::Data, IAC) => TelnetState::Iac,
+                (TelnetState::Data, _) => {
+                    data.push_back(byte);
+                    TelnetState::Data
+                }
+                (TelnetState::Iac, IAC) => {
+                    data.push_back(IAC);
+                    TelnetState::Data
+                }
+                (TelnetState::Iac, WILL | WONT | DO | DONT) => TelnetState::Negotiation(byte),
+                (TelnetState::Iac, SB) => {
+                    self.subnegotiation.clear();
+                    TelnetState::Subnegotiation
+                }
+                // Other commands such as NOP carry no information for a serial link
+                (TelnetState::Iac, _) => TelnetState::Data,
+                (TelnetState::Negotiation(command), _) => {
+                    events.push(TelnetEvent::Negotiation(*command, byte));
+                    TelnetState::Data
+                }
+                (TelnetState::Subnegotiation, IAC) => TelnetState::SubnegotiationIac,
+                (TelnetState::Subnegotiation, _) => {
+                    self.subnegotiation.push(byte);
+                    TelnetState::Subnegotiation
+                }
+                (TelnetState::SubnegotiationIac, IAC) => {
+                    self.subnegotiation.push(IAC);
+                    TelnetState::Subnegotiation
+                }
+                (TelnetState::SubnegotiationIac, SE) => {
+                    events.push(TelnetEvent::Subnegotiation(std::mem::take(
+                        &mut self.subnegotiation,
+                    )));
+                    TelnetState::Data
+                }
+                (TelnetState::SubnegotiationIac, _) => TelnetState::Data,
+            };
+        }
+    }
+}
+
+/// Doubles the IAC bytes so that they are sent as data.
+fn escape(bytes: &[u8], out: &mut Vec<u8>) {
+    for &byte in bytes {
+        out.push(byte);
+        if byte == IAC {
+            out.push(IAC);
+        }
+    }
+}
+
+fn subnegotiation(code: u8, payload: &[u8]) -> Vec<u8> {
+    let mut message = vec![IAC, SB, COM_PORT_OPTION, code];
+    escape(payload, &mut message);
+    message.extend([IAC, SE]);
+    message
+}
+
+/// Transport to a lidar behind a device server speaking RFC 2217 (serial port over
+/// telnet). Unlike a plain TCP bridge, the baud rate and the control lines of the
+/// remote serial port are set from here.
+///
+/// `run_driver` and `DriverBuilder` connect to such endpoints when given a port name
+/// of the form `rfc2217://host:port`, with the baud rate of the model.
+pub struct Rfc2217Transport {
+    address: String,
+    stream: TcpStream,
+    timeout: Duration,
+    baud_rate: u32,
+    dtr: Option<bool>,
+    rts: Option<bool>,
+    decoder: TelnetDecoder,
+    events: Vec<TelnetEvent>,
+    /// Data bytes decoded from the stream but not read yet.
+    pending: VecDeque<u8>,
+    /// Code of the server reply `wait_for_reply` is waiting for, and its payload once
+    /// received.
+    awaited: Option<u8>,
+    reply: Option<Vec<u8>>,
+    refused: bool,
+}
+
+impl Rfc2217Transport {
+    /// Connects to the device server and configures its serial port with the given baud
+    /// rate, 8 data bits, no parity and 1 stop bit.
+    /// # Arguments
+    ///
+    /// * `address` - Endpoint such as `192.168.1.10:4001`.
+    /// * `baud_rate` - Baud rate, see `model_baud_rate`.
+    pub fn connect(address: &str, baud_rate: u32) -> Result<Self, YDLidarError> {
+        let timeout = Duration::from_millis(10);
+        let mut transport = Rfc2217Transport {
+            address: address.to_string(),
+            stream: connect(address, timeout)?,
+            timeout,
+            baud_rate,
+            dtr: None,
+            rts: None,
+            decoder: TelnetDecoder::new(),
+            events: Vec::new(),
+            pending: VecDeque::new(),
+            awaited: None,
+            reply: None,
+            refused: false,
+        };
+        transport.negotiate()?;
+        Ok(transport)
+    }
+
+    /// Sets the DTR line of the remote serial port.
+    pub fn set_dtr(&mut self, on: bool) -> Result<(), YDLidarError> {
+        self.command(SET_CONTROL, &[if on { DTR_ON } else { DTR_OFF }])?;
+        self.dtr = Some(on);
+        Ok(())
+    }
+
+    /// Sets the RTS line of the remote serial port.
+    pub fn set_rts(&mut self, on: bool) -> Result<(), YDLidarError> {
+        self.command(SET_CONTROL, &[if on { RTS_ON } else { RTS_OFF }])?;
+        self.rts = Some(on);
+        Ok(())
+    }
+
+    /// Drops the bytes buffered by the device server in both directions, as well as
+    /// the bytes received but not read yet.
+    pub fn purge(&mut self) -> Result<(), YDLidarError> {
+        self.command(PURGE_DATA, &[PURGE_BOTH])?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Announces the com port option and configures the remote serial port.
+    /// The control lines are restored after a reconnection.
+    fn negotiate(&mut self) -> Result<(), YDLidarError> {
+        self.stream.write_all(&[
+            IAC,
+            WILL,
+            COM_PORT_OPTION,
+            IAC,
+            WILL,
+            BINARY,
+            IAC,
+            DO,
+            BINARY,
+            IAC,
+            WILL,
+            SUPPRESS_GO_AHEAD,
+            IAC,
+            DO,
+            SUPPRESS_GO_AHEAD,
+        ])?;
+        self.command(SET_BAUDRATE, &self.baud_rate.to_be_bytes())?;
+        self.command(SET_DATASIZE, &[8])?;
+        self.command(SET_PARITY, &[PARITY_NONE])?;
+        self.command(SET_STOPSIZE, &[STOPSIZE_1])?;
+        if let Some(on) = self.dtr {
+            self.set_dtr(on)?;
+        }
+        if let Some(on) = self.rts {
+            self.set_rts(on)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a com port subcommand and waits for the server to acknowledge it.
+    fn command(&mut self, code: u8, payload: &[u8]) -> Result<Vec<u8>, YDLidarError> {
+        self.stream.write_all(&subnegotiation(code, payload))?;
+        self.awaited = Some(code + SERVER_OFFSET);
+        self.reply = None;
+        let result = self.wait_for_reply();
+        self.awaited = None;
+        result
+    }
+
+    fn wait_for_reply(&mut self) -> Result<Vec<u8>, YDLidarError> {
+        let deadline = Instant::now() + Duration::from_millis(CONNECT_TIMEOUT);
+        loop {
+            if let Some(reply) = self.reply.take() {
+                return Ok(reply);
+            }
+            if self.refused {
+                return Err(YDLidarError::ConnectionFailed(
+                    self.address.clone(),
+                    io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "the server does not support RFC 2217",
+                    ),
+                ));
+            }
+            if Instant::now() >= deadline {
+                return Err(YDLidarError::TimeoutError());
+            }
+            match self.receive() {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(_) => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::TimedOut
+                            | io::ErrorKind::WouldBlock
+                            | io::ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Reads the socket once and decodes what was received.
+    /// Returns the number of raw bytes read, data and commands alike.
+    fn receive(&mut self) -> io::Result<usize> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        let n_read = self.stream.read(&mut chunk)?;
+        self.decoder
+            .decode(&chunk[..n_read], &mut self.pending, &mut self.events);
+        self.handle_events()?;
+        Ok(n_read)
+    }
+
+    fn handle_events(&mut self) -> io::Result<()> {
+        for event in std::mem::take(&mut self.events) {
+            match event {
+                // Acknowledgements of the options announced in `negotiate`
+                TelnetEvent::Negotiation(DO | WILL, BINARY | SUPPRESS_GO_AHEAD) => {}
+                TelnetEvent::Negotiation(DO, COM_PORT_OPTION) => {}
+                TelnetEvent::Negotiation(DONT, COM_PORT_OPTION) => self.refused = true,
+                TelnetEvent::Negotiation(DO, option) => {
+                    self.stream.write_all(&[IAC, WONT, option])?;
+                }
+                TelnetEvent::Negotiation(WILL, option) => {
+                    self.stream.write_all(&[IAC, DONT, option])?;
+                }
+                TelnetEvent::Negotiation(_, _) => {}
+                TelnetEvent::Subnegotiation(bytes) => {
+                    if let [COM_PORT_OPTION, code, payload @ ..] = bytes.as_slice() {
+                        if self.awaited == Some(*code) {
+                            self.reply = Some(payload.to_vec());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transport for Rfc2217Transport {
+    fn name(&self) -> String {
+        format!("rfc2217://{}", self.address)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            match self.receive() {
+                Ok(0) => return Ok(0),
+                // Only telnet commands were received
+                Ok(_) => {}
+                // Sockets report an elapsed read timeout as WouldBlock on unix
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Err(io::ErrorKind::TimedOut.into())
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let n_read = buf.len().min(self.pending.len());
+        for (dst, byte) in buf.iter_mut().zip(self.pending.drain(..n_read)) {
+            *dst = byte;
+        }
+        Ok(n_read)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut escaped = Vec::with_capacity(data.len());
+        escape(data, &mut escaped);
+        self.stream.write_all(&escaped)?;
+        Ok(data.len())
+    }
+
+    fn bytes_available(&mut self) -> Result<usize, YDLidarError> {
+        self.stream.set_nonblocking(true)?;
+        let result = loop {
+            match self.receive() {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+        Ok(self.pending.len())
+    }
+
+    /// Also drops the bytes buffered by the device server.
+    fn flush(&mut self) -> Result<(), YDLidarError> {
+        self.command(PURGE_DATA, &[PURGE_RECEIVE])?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), YDLidarError> {
+        self.stream.set_read_timeout(Some(non_zero(timeout)))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    /// Sends the baud rate to the device server, unless it was already negotiated.
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), YDLidarError> {
+        if baud_rate == self.baud_rate {
+            return Ok(());
+        }
+        self.command(SET_BAUDRATE, &baud_rate.to_be_bytes())?;
+        self.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    /// Connects to the device server again and restores the serial port settings.
+    fn reconnect(&mut self) -> Result<(), YDLidarError> {
+        self.stream = connect(&self.address, self.timeout)?;
+        self.decoder = TelnetDecoder::new();
+        self.pending.clear();
+        self.refused = false;
+        self.negotiate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::DriverEvent;
+    use crate::run_driver;
+    use crate::test_util::{KeepInput, LAP_START};
+    use crossbeam_channel::{unbounded, Receiver, Sender};
+    use std::net::TcpListener;
+    use ydlidar_data::{model_baud_rate, YdlidarModel};
+
+    enum ServerEvent {
+        Command(u8, Vec<u8>),
+        Data(Vec<u8>),
+    }
+
+    /// Minimal RFC 2217 server: acknowledges every com port subcommand, reports what
+    /// it receives and sends the bytes it is given.
+    fn spawn_server() -> (String, Receiver<ServerEvent>, Sender<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (event_tx, event_rx) = unbounded();
+        let (data_tx, data_rx) = unbounded::<Vec<u8>>();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(5)))
+                .unwrap();
+            let mut decoder = TelnetDecoder::new();
+            let mut events = Vec::new();
+            let mut data = VecDeque::new();
+            let mut chunk = [0u8; 256];
+            loop {
+                while let Ok(bytes) = data_rx.try_recv() {
+                    let mut escaped = Vec::new();
+                    escape(&bytes, &mut escaped);
+                    stream.write_all(&escaped).unwrap();
+                }
+                let n_read = match stream.read(&mut chunk) {
+                    Ok(0) => return,
+                    Ok(n_read) => n_read,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(_) => return,
+                };
+                decoder.decode(&chunk[..n_read], &mut data, &mut events);
+                if !data.is_empty() {
+                    let _ = event_tx.send(ServerEvent::Data(data.drain(..).collect()));
+                }
+                for event in events.drain(..) {
+                    match event {
+                        TelnetEvent::Negotiation(WILL, COM_PORT_OPTION) => {
+                            stream.write_all(&[IAC, DO, COM_PORT_OPTION]).unwrap();
+                        }
+                        TelnetEvent::Negotiation(_, _) => {}
+                        TelnetEvent::Subnegotiation(bytes) => {
+                            let code = bytes[1];
+                            let payload = bytes[2..].to_vec();
+                            stream
+                                .write_all(&subnegotiation(code + SERVER_OFFSET, &payload))
+                                .unwrap();
+                            let _ = event_tx.send(ServerEvent::Command(code, payload));
+                        }
+                    }
+                }
+            }
+        });
+        (address, event_rx, data_tx)
+    }
+
+    fn recv_command(event_rx: &Receiver<ServerEvent>) -> (u8, Vec<u8>) {
+        match event_rx.recv().unwrap() {
+            ServerEvent::Command(code, payload) => (code, payload),
+            ServerEvent::Data(data) => panic!("Expected a command but received {:?}", data),
+        }
+    }
+
+    #[test]
+    fn test_decode_telnet() {
+        let mut decoder = TelnetDecoder::new();
+        let mut data = VecDeque::new();
+        let mut events = Vec::new();
+        decoder.decode(
+            &[0x01, IAC, IAC, IAC, DO, 24, IAC, SB, COM_PORT_OPTION],
+            &mut data,
+            &mut events,
+        );
+        // A subnegotiation split across reads
+        decoder.decode(&[101, IAC, IAC, IAC, SE, 0x02], &mut data, &mut events);
+        assert_eq!(data, [0x01, IAC, 0x02]);
+        assert!(matches!(
+            events.as_slice(),
+            [
+                TelnetEvent::Negotiation(DO, 24),
+                TelnetEvent::Subnegotiation(bytes)
+            ] if bytes == &[COM_PORT_OPTION, 101, IAC]
+        ));
+    }
+
+    #[test]
+    fn test_control() {
+        let (address, event_rx, data_tx) = spawn_server();
+        let mut transport = Rfc2217Transport::connect(&address, 230400).unwrap();
+        assert_eq!(
+            recv_command(&event_rx),
+            (SET_BAUDRATE, 230400u32.to_be_bytes().to_vec())
+        );
+        assert_eq!(recv_command(&event_rx), (SET_DATASIZE, vec![8]));
+        assert_eq!(recv_command(&event_rx), (SET_PARITY, vec![PARITY_NONE]));
+        assert_eq!(recv_command(&event_rx), (SET_STOPSIZE, vec![STOPSIZE_1]));
+
+        // The negotiated baud rate is not sent again
+        transport.set_baud_rate(230400).unwrap();
+        transport.set_baud_rate(115200).unwrap();
+        assert_eq!(
+            recv_command(&event_rx),
+            (SET_BAUDRATE, 115200u32.to_be_bytes().to_vec())
+        );
+        transport.set_dtr(true).unwrap();
+        assert_eq!(recv_command(&event_rx), (SET_CONTROL, vec![DTR_ON]));
+        transport.set_rts(false).unwrap();
+        assert_eq!(recv_command(&event_rx), (SET_CONTROL, vec![RTS_OFF]));
+        transport.purge().unwrap();
+        assert_eq!(recv_command(&event_rx), (PURGE_DATA, vec![PURGE_BOTH]));
+
+        // IAC bytes go through as data in both directions
+        Transport::write(&mut transport, &[0xA5, IAC]).unwrap();
+        assert!(matches!(
+            event_rx.recv().unwrap(),
+            ServerEvent::Data(data) if data == [0xA5, IAC]
+        ));
+        data_tx.send(vec![IAC, 0x55]).unwrap();
+        let mut buf = [0u8; 2];
+        transport.set_timeout(Duration::from_millis(100)).unwrap();
+        let mut n_read = 0;
+        while n_read < 2 {
+            n_read += Transport::read(&mut transport, &mut buf[n_read..]).unwrap();
+        }
+        assert_eq!(buf, [IAC, 0x55]);
+    }
+
+    #[test]
+    fn test_run_driver() {
+        let (address, event_rx, data_tx) = spawn_server();
+        data_tx.send(LAP_START.to_vec()).unwrap();
+        let port = KeepInput::open(format!("rfc2217://{address}"), YdlidarModel::TMiniPro);
+        let (thread, scan_rx) = run_driver(port, YdlidarModel::TMiniPro, 200, 10, 0, 10).unwrap();
+
+        let baud_rate = model_baud_rate(YdlidarModel::TMiniPro);
+        assert_eq!(
+            recv_command(&event_rx),
+            (SET_BAUDRATE, baud_rate.to_be_bytes().to_vec())
+        );
+        assert_eq!(recv_command(&event_rx), (SET_DATASIZE, vec![8]));
+        assert_eq!(recv_command(&event_rx), (SET_PARITY, vec![PARITY_NONE]));
+        assert_eq!(recv_command(&event_rx), (SET_STOPSIZE, vec![STOPSIZE_1]));
+        assert!(matches!(scan_rx.recv().unwrap(), DriverEvent::Scan(_)));
+        // The driver does not set the baud rate a second time
+        assert!(event_rx.try_recv().is_err());
+
+        drop(thread);
+    }
+}
@@ -0,0 +1,149 @@
+/// Byte buffer. When full, the oldest bytes are overwritten, unless the buffer grows.
+pub(crate) struct RingBuffer {
+    data: Box<[u8]>,
+    head: usize,
+    len: usize,
+    growable: bool,
+}
+
+impl RingBuffer {
+    /// Buffer of a fixed capacity.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        RingBuffer {
+            data: vec![0; capacity].into_boxed_slice(),
+            head: 0,
+            len: 0,
+            growable: false,
+        }
+    }
+
+    /// Buffer that grows to hold every byte pushed and not consumed yet.
+    pub(crate) fn growable(capacity: usize) -> Self {
+        RingBuffer {
+            growable: true,
+            ..RingBuffer::with_capacity(capacity)
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<u8> {
+        if index >= self.len {
+            return None;
+        }
+        Some(self.data[(self.head + index) % self.capacity()])
+    }
+
+    /// Appends bytes at the end of the buffer.
+    /// Returns the number of old bytes that were overwritten to make room for them.
+    pub(crate) fn push_slice(&mut self, bytes: &[u8]) -> usize {
+        if self.growable && self.len + bytes.len() > self.capacity() {
+            self.grow((self.len + bytes.len()).max(2 * self.capacity()));
+        }
+        let capacity = self.capacity();
+        // Only the last `capacity` bytes can be kept
+        let skipped = bytes.len().saturating_sub(capacity);
+        let bytes = &bytes[skipped..];
+
+        let overwritten = (self.len + bytes.len()).saturating_sub(capacity);
+        self.consume(overwritten);
+
+        let tail = (self.head + self.len) % capacity;
+        let first = bytes.len().min(capacity - tail);
+        self.data[tail..tail + first].copy_from_slice(&bytes[..first]);
+        self.data[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+        self.len += bytes.len();
+        skipped + overwritten
+    }
+
+    fn grow(&mut self, capacity: usize) {
+        let mut data = vec![0; capacity].into_boxed_slice();
+        self.copy_from(0, &mut data[..self.len]);
+        self.data = data;
+        self.head = 0;
+    }
+
+    /// Copies the `dst.len()` bytes starting at `start` into `dst` without consuming them.
+    pub(crate) fn copy_from(&self, start: usize, dst: &mut [u8]) {
+        assert!(start + dst.len() <= self.len);
+        let capacity = self.capacity();
+        let head = (self.head + start) % capacity;
+        let first = dst.len().min(capacity - head);
+        let second = dst.len() - first;
+        dst[..first].copy_from_slice(&self.data[head..head + first]);
+        dst[first..].copy_from_slice(&self.data[..second]);
+    }
+
+    /// Removes the first `n` bytes of the buffer.
+    pub(crate) fn consume(&mut self, n: usize) {
+        let n = n.min(self.len);
+        self.head = (self.head + n) % self.capacity();
+        self.len -= n;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_consume() {
+        let mut buffer = RingBuffer::with_capacity(4);
+        assert_eq!(buffer.len(), 0);
+        assert_eq!(buffer.push_slice(&[1, 2, 3]), 0);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.get(0), Some(1));
+        assert_eq!(buffer.get(3), None);
+
+        buffer.consume(2);
+        // Wraps around the end of the storage
+        assert_eq!(buffer.push_slice(&[4, 5, 6]), 0);
+        let mut dst = [0u8; 4];
+        buffer.copy_from(0, &mut dst);
+        assert_eq!(dst, [3, 4, 5, 6]);
+        let mut dst = [0u8; 2];
+        buffer.copy_from(1, &mut dst);
+        assert_eq!(dst, [4, 5]);
+
+        buffer.clear();
+        assert_eq!(buffer.len(), 0);
+    }
+
+    #[test]
+    fn test_overwrite_oldest() {
+        let mut buffer = RingBuffer::with_capacity(4);
+        buffer.push_slice(&[1, 2, 3]);
+        assert_eq!(buffer.push_slice(&[4, 5]), 1);
+        let mut dst = [0u8; 4];
+        buffer.copy_from(0, &mut dst);
+        assert_eq!(dst, [2, 3, 4, 5]);
+
+        assert_eq!(buffer.push_slice(&[6, 7, 8, 9, 10, 11]), 6);
+        buffer.copy_from(0, &mut dst);
+        assert_eq!(dst, [8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn test_grow() {
+        let mut buffer = RingBuffer::growable(4);
+        buffer.push_slice(&[1, 2, 3]);
+        buffer.consume(2);
+        assert_eq!(buffer.push_slice(&[4, 5, 6, 7, 8, 9, 10, 11, 12]), 0);
+        assert_eq!(buffer.len(), 10);
+        let mut dst = [0u8; 10];
+        buffer.copy_from(0, &mut dst);
+        assert_eq!(dst, [3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+    }
+}
@@ -1,7 +1,10 @@
 use ydlidar_data::scan::Scan;
+use ydlidar_data::InterferenceCounts;
 
 pub(crate) trait YdLidarScan {
     fn new() -> Self;
+    /// Empties the scan while keeping its allocated storage.
+    fn reset(&mut self);
 }
 
 impl YdLidarScan for Scan {
@@ -9,7 +12,43 @@ impl YdLidarScan for Scan {
         Scan {
             angles_radian: Vec::new(),
             distances: Vec::new(),
+            intensities: None,
+            interference_flags: None,
+            interference_counts: InterferenceCounts::default(),
             checksum_correct: true,
+            point_checksum_correct: Vec::new(),
+            packets_accepted: 0,
+            packets_rejected: 0,
+            sequence: 0,
+            first_packet_instant: None,
+            last_packet_instant: None,
+            first_packet_time: None,
+            last_packet_time: None,
+            scan_frequency: None,
+            discarded_bytes: 0,
         }
     }
+
+    fn reset(&mut self) {
+        self.angles_radian.clear();
+        self.distances.clear();
+        if let Some(intensities) = &mut self.intensities {
+            intensities.clear();
+        }
+        if let Some(flags) = &mut self.interference_flags {
+            flags.clear();
+        }
+        self.interference_counts = InterferenceCounts::default();
+        self.checksum_correct = true;
+        self.point_checksum_correct.clear();
+        self.packets_accepted = 0;
+        self.packets_rejected = 0;
+        self.sequence = 0;
+        self.first_packet_instant = None;
+        self.last_packet_instant = None;
+        self.first_packet_time = None;
+        self.last_packet_time = None;
+        self.scan_frequency = None;
+        self.discarded_bytes = 0;
+    }
 }
@@ -1,10 +1,64 @@
 use crate::constants::{LIDAR_CMD_SYNC_BYTE, N_READ_TRIALS};
 use crate::error::YDLidarError;
 use crate::time::sleep_ms;
+use crate::transport::Transport;
 use serialport::SerialPort;
-use std::io::Read;
+use std::time::{Duration, Instant};
 
-pub(crate) fn start_scan(_: &mut Box<dyn SerialPort>) -> Result<(), YDLidarError> {
+pub(crate) fn open_port(
+    port_name: &str,
+    baud_rate: u32,
+) -> Result<Box<dyn SerialPort>, YDLidarError> {
+    let maybe_port = serialport::new(port_name, baud_rate)
+        .timeout(Duration::from_millis(10))
+        .open();
+
+    maybe_port.map_err(|e| open_error(port_name, baud_rate, e))
+}
+
+/// Converts an error raised when opening a port.
+pub(crate) fn open_error(port_name: &str, baud_rate: u32, e: serialport::Error) -> YDLidarError {
+    match e.kind() {
+        serialport::ErrorKind::NoDevice if is_busy(&e) => {
+            YDLidarError::PortBusy(port_name.to_string())
+        }
+        serialport::ErrorKind::NoDevice
+        | serialport::ErrorKind::Io(std::io::ErrorKind::NotFound) => {
+            YDLidarError::PortNotFound(port_name.to_string())
+        }
+        serialport::ErrorKind::Io(std::io::ErrorKind::PermissionDenied) => {
+            YDLidarError::PortPermissionDenied(port_name.to_string())
+        }
+        serialport::ErrorKind::InvalidInput
+        | serialport::ErrorKind::Io(std::io::ErrorKind::InvalidInput) => {
+            YDLidarError::InvalidBaudRate(baud_rate)
+        }
+        _ => YDLidarError::PortOpenError(port_name.to_string(), e),
+    }
+}
+
+/// `serialport` reports a port in use (EBUSY or a failed lock) with the same kind as a
+/// missing device, and only tells them apart in the description.
+fn is_busy(e: &serialport::Error) -> bool {
+    let description = e.description.to_lowercase();
+    description.contains("busy") || description.contains("lock")
+}
+
+/// Waits until the device sends its first bytes.
+pub(crate) fn wait_for_data(port: &mut dyn Transport, timeout: u64) -> Result<(), YDLidarError> {
+    let start = Instant::now();
+    loop {
+        if get_n_read(port)? > 0 {
+            return Ok(());
+        }
+        if start.elapsed() >= Duration::from_millis(timeout) {
+            return Err(YDLidarError::NoDataReceived(timeout));
+        }
+        sleep_ms(10);
+    }
+}
+
+pub(crate) fn start_scan(_: &mut dyn Transport) -> Result<(), YDLidarError> {
     // The X2 lidar does not support commands
     //send_command(port, LIDAR_CMD_SCAN)?;
     // let packet = read(port, PACKET_HEADER_SIZE)?;
@@ -13,47 +67,43 @@ pub(crate) fn start_scan(_: &mut Box<dyn SerialPort>) -> Result<(), YDLidarError
     Ok(())
 }
 
-fn stop_scan(_: &mut Box<dyn SerialPort>) -> Result<(), YDLidarError> {
+fn stop_scan(_: &mut dyn Transport) -> Result<(), YDLidarError> {
     // The X2 lidar does not support commands
     //send_command(port, LIDAR_CMD_FORCE_STOP)?;
     //send_command(port, LIDAR_CMD_STOP)?;
     Ok(())
 }
 
-pub(crate) fn stop_scan_and_flush(port: &mut Box<dyn SerialPort>) -> Result<(), YDLidarError> {
+pub(crate) fn stop_scan_and_flush(port: &mut dyn Transport) -> Result<(), YDLidarError> {
     stop_scan(port)?;
-    flush(port)?;
+    port.flush()?;
     Ok(())
 }
 
-fn send_data(port: &mut Box<dyn SerialPort>, data: &[u8]) -> std::io::Result<usize> {
+/// Stops any running scan, drops the pending data and starts scanning again.
+/// Used when the driver starts, after a reconnection and when the watchdog restarts
+/// the scan.
+pub(crate) fn reset_scan(port: &mut dyn Transport) -> Result<(), YDLidarError> {
+    stop_scan_and_flush(port)?;
+    sleep_ms(10);
+    stop_scan_and_flush(port)?;
+    start_scan(port)
+}
+
+fn send_data(port: &mut dyn Transport, data: &[u8]) -> std::io::Result<usize> {
     port.write(data)
 }
 
-pub(crate) fn send_command(port: &mut Box<dyn SerialPort>, command: u8) -> std::io::Result<usize> {
+pub(crate) fn send_command(port: &mut dyn Transport, command: u8) -> std::io::Result<usize> {
     let data: [u8; 2] = [LIDAR_CMD_SYNC_BYTE, command];
     send_data(port, &data)
 }
 
-pub(crate) fn get_n_read(port: &mut Box<dyn SerialPort>) -> Result<usize, YDLidarError> {
-    let n_u32: u32 = port.bytes_to_read()?;
-    Ok(n_u32.try_into().unwrap_or(0))
-}
-
-pub(crate) fn flush(port: &mut Box<dyn SerialPort>) -> Result<(), YDLidarError> {
-    let n_read: usize = get_n_read(port).unwrap_or(0);
-    if n_read == 0 {
-        return Ok(());
-    }
-    let mut packet: Vec<u8> = vec![0; n_read];
-    port.read_exact(packet.as_mut_slice())?;
-    Ok(())
+pub(crate) fn get_n_read(port: &mut dyn Transport) -> Result<usize, YDLidarError> {
+    port.bytes_available()
 }
 
-pub(crate) fn read(
-    port: &mut Box<dyn SerialPort>,
-    data_size: usize,
-) -> Result<Vec<u8>, YDLidarError> {
+pub(crate) fn read(port: &mut dyn Transport, data_size: usize) -> Result<Vec<u8>, YDLidarError> {
     assert!(data_size > 0);
     for _ in 0..N_READ_TRIALS {
         let n_read: usize = get_n_read(port)?;
@@ -86,7 +136,7 @@ mod tests {
 
         sleep_ms(10);
         let mut buf = [0u8; 2];
-        slave.read(&mut buf).unwrap();
+        slave.read_exact(&mut buf).unwrap();
         assert_eq!(buf, [0xA5, 0x68]);
     }
 
@@ -103,7 +153,7 @@ mod tests {
     //     sleep_ms(10);
     //
     //     let mut buf = [0u8; 2];
-    //     master.read(&mut buf).unwrap();
+    //     master.read_exact(&mut buf).unwrap();
     //     assert_eq!(buf, [0xA5, 0x60]);
     // }
     //
@@ -116,15 +166,67 @@ mod tests {
     //     sleep_ms(10);
     //
     //     let mut buf = [0u8; 4];
-    //     slave.read(&mut buf).unwrap();
+    //     slave.read_exact(&mut buf).unwrap();
     //     assert_eq!(buf, [0xA5, 0x00, 0xA5, 0x65]);
     // }
 
+    #[test]
+    fn test_open_port() {
+        assert!(matches!(
+            open_port("/dev/ydlidar-does-not-exist", 115200),
+            Err(YDLidarError::PortNotFound(_))
+        ));
+
+        let (_master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let name = slave.name().unwrap();
+        let _port = open_port(&name, 115200).unwrap();
+        assert!(matches!(
+            open_port(&name, 115200),
+            Err(YDLidarError::PortBusy(_))
+        ));
+    }
+
+    #[test]
+    fn test_open_error() {
+        let error =
+            |description| serialport::Error::new(serialport::ErrorKind::NoDevice, description);
+        assert!(matches!(
+            open_error("port", 115200, error("Device or resource busy")),
+            YDLidarError::PortBusy(_)
+        ));
+        assert!(matches!(
+            open_error(
+                "port",
+                115200,
+                error("Unable to acquire exclusive lock on serial port")
+            ),
+            YDLidarError::PortBusy(_)
+        ));
+        assert!(matches!(
+            open_error("port", 115200, error("Device not configured")),
+            YDLidarError::PortNotFound(_)
+        ));
+    }
+
+    #[test]
+    fn test_wait_for_data() {
+        let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
+        let mut slave_ptr = Box::new(slave) as Box<dyn SerialPort>;
+
+        assert!(matches!(
+            wait_for_data(&mut slave_ptr, 50),
+            Err(YDLidarError::NoDataReceived(50))
+        ));
+
+        master.write_all(&[0xAA, 0x55]).unwrap();
+        assert!(wait_for_data(&mut slave_ptr, 50).is_ok());
+    }
+
     #[test]
     fn test_flush() {
         let (mut master, slave) = TTYPort::pair().expect("Unable to create ptty pair");
         master
-            .write(&[0xA5, 0x5A, 0x03, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00])
+            .write_all(&[0xA5, 0x5A, 0x03, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00])
             .unwrap();
 
         let mut slave_ptr = Box::new(slave) as Box<dyn SerialPort>;
@@ -132,11 +234,11 @@ mod tests {
         sleep_ms(10);
 
         assert_eq!(slave_ptr.bytes_to_read().unwrap(), 10);
-        flush(&mut slave_ptr).unwrap();
+        Transport::flush(&mut slave_ptr).unwrap();
         assert_eq!(slave_ptr.bytes_to_read().unwrap(), 0);
 
         // when zero bytes to read
-        flush(&mut slave_ptr).unwrap();
+        Transport::flush(&mut slave_ptr).unwrap();
         assert_eq!(slave_ptr.bytes_to_read().unwrap(), 0);
     }
 }
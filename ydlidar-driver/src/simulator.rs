@@ -0,0 +1,461 @@
+use crate::encoder::PacketEncoder;
+use crate::error::YDLidarError;
+use crate::numeric::{correct_angle, degree_to_radian, to_angle};
+use crate::packet::{encode_angle, lap_scan_frequency, sample_angle, SampleFormat};
+use crate::scan::YdLidarScan;
+use ydlidar_data::{model_max_distance, model_sample_rate, InterferenceFlag, Scan, YdlidarModel};
+
+/// Obstacle of a simulated world. Coordinates are in mm.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    /// Wall between two points.
+    Segment { start: [f64; 2], end: [f64; 2] },
+    /// Round obstacle, seen from the outside or, around the lidar, from the inside.
+    Circle { center: [f64; 2], radius: f64 },
+}
+
+impl Shape {
+    /// Walls of the closed polygon going through `vertices`.
+    pub fn polygon(vertices: &[[f64; 2]]) -> Vec<Shape> {
+        (0..vertices.len())
+            .map(|i| Shape::Segment {
+                start: vertices[i],
+                end: vertices[(i + 1) % vertices.len()],
+            })
+            .collect()
+    }
+
+    /// Distance along the ray to the first intersection with the shape.
+    /// `direction` is a unit vector.
+    fn intersect(&self, origin: [f64; 2], direction: [f64; 2]) -> Option<f64> {
+        match self {
+            Shape::Segment { start, end } => {
+                let edge = sub(*end, *start);
+                let denominator = cross(direction, edge);
+                if denominator.abs() < f64::EPSILON {
+                    // Parallel to the wall
+                    return None;
+                }
+                let w = sub(*start, origin);
+                let t = cross(w, edge) / denominator;
+                let s = cross(w, direction) / denominator;
+                (t > 0. && (0. ..=1.).contains(&s)).then_some(t)
+            }
+            Shape::Circle { center, radius } => {
+                let f = sub(origin, *center);
+                let b = dot(f, direction);
+                let discriminant = b * b - (dot(f, f) - radius * radius);
+                if discriminant < 0. {
+                    return None;
+                }
+                let root = discriminant.sqrt();
+                [-b - root, -b + root].into_iter().find(|&t| t > 0.)
+            }
+        }
+    }
+}
+
+fn sub(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn dot(a: [f64; 2], b: [f64; 2]) -> f64 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn cross(a: [f64; 2], b: [f64; 2]) -> f64 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+/// Position of the simulated lidar, in mm, and direction of its 0 angle in radians,
+/// counterclockwise from the x axis like the angles of a `Scan`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pose {
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64,
+}
+
+/// One lap produced by a [`Simulator`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulatedLap {
+    /// Packets of the lap as sent by the device, starting with the lap start packet.
+    pub bytes: Vec<u8>,
+    /// Scan the driver decodes from `bytes` with its default distance limits: samples
+    /// that hit nothing within the range of the model are left out. Laps are numbered
+    /// from 1, after the empty lap the driver starts with. The reception times are
+    /// left out, as only the driver knows them.
+    pub scan: Scan,
+}
+
+/// Lidar simulator producing the byte stream of a device placed in a 2D world.
+///
+/// Each lap is made of a lap start packet holding the sample at 0 degree, followed by
+/// packets of up to 40 samples. The number of samples per lap follows from the sample
+/// rate of the model and the scan frequency. Samples are ray-cast against the shapes
+/// at the angles the driver decodes, X2 angle correction included, so that the
+/// decoded laps match the ground truth of `SimulatedLap::scan`.
+///
+/// ```
+/// use ydlidar_data::YdlidarModel;
+/// use ydlidar_driver::{ScanDecoder, Shape, Simulator};
+///
+/// let room = Shape::polygon(&[[-2000., -1500.], [2000., -1500.], [2000., 1500.], [-2000., 1500.]]);
+/// let mut simulator = Simulator::new(YdlidarModel::X2, room).noise(10.);
+/// let mut decoder = ScanDecoder::new(YdlidarModel::X2);
+/// let lap = simulator.lap();
+/// decoder.push(&lap.bytes);
+/// decoder.push(&simulator.lap().bytes);
+/// // The decoder starts with an empty lap
+/// decoder.next_scan().unwrap();
+/// assert_eq!(decoder.next_scan().unwrap(), lap.scan);
+/// ```
+pub struct Simulator {
+    model: YdlidarModel,
+    encoder: PacketEncoder,
+    shapes: Vec<Shape>,
+    pose: Pose,
+    scan_frequency: f64,
+    noise: f64,
+    rng: XorShift,
+    n_laps: u64,
+}
+
+impl Simulator {
+    /// Creates a simulator at the origin of the world, turning at 7 Hz without noise.
+    pub fn new(model: YdlidarModel, shapes: Vec<Shape>) -> Self {
+        Simulator {
+            model,
+            encoder: PacketEncoder::new(model).scan_frequency(7.),
+            shapes,
+            pose: Pose::default(),
+            scan_frequency: 7.,
+            noise: 0.,
+            rng: XorShift::new(0x2545_F491_4F6C_DD1D),
+            n_laps: 0,
+        }
+    }
+
+    /// Initial pose of the lidar. Defaults to the origin, facing the x axis.
+    pub fn pose(mut self, pose: Pose) -> Self {
+        self.pose = pose;
+        self
+    }
+
+    /// Rotation speed in laps per second, also sent in the lap start packets.
+    /// Defaults to 7. Fails if the frequency is not positive.
+    pub fn scan_frequency(mut self, scan_frequency: f64) -> Result<Self, YDLidarError> {
+        if !(scan_frequency.is_finite() && scan_frequency > 0.) {
+            return Err(YDLidarError::InvalidConfiguration(format!(
+                "scan_frequency ({scan_frequency}) must be greater than 0"
+            )));
+        }
+        self.scan_frequency = scan_frequency;
+        self.encoder = PacketEncoder::new(self.model).scan_frequency(scan_frequency);
+        Ok(self)
+    }
+
+    /// Standard deviation of the gaussian noise added to the distances, in mm.
+    /// Defaults to 0.
+    pub fn noise(mut self, noise: f64) -> Self {
+        self.noise = noise;
+        self
+    }
+
+    /// Seed of the noise, to get other reproducible sequences.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = XorShift::new(seed);
+        self
+    }
+
+    /// Moves the lidar, e.g. between laps.
+    pub fn set_pose(&mut self, pose: Pose) {
+        self.pose = pose;
+    }
+
+    /// Distance in mm from the lidar to the closest shape in the direction `angle_radian`,
+    /// or `None` if there is nothing within the range of the model.
+    pub fn range(&self, angle_radian: f64) -> Option<f64> {
+        let direction = self.pose.heading + angle_radian;
+        let direction = [direction.cos(), direction.sin()];
+        let origin = [self.pose.x, self.pose.y];
+        self.shapes
+            .iter()
+            .filter_map(|shape| shape.intersect(origin, direction))
+            .min_by(f64::total_cmp)
+            .filter(|&distance| distance <= model_max_distance(self.model) as f64)
+    }
+
+    /// Simulates the next lap.
+    pub fn lap(&mut self) -> SimulatedLap {
+        let n_samples =
+            ((model_sample_rate(self.model) as f64 / self.scan_frequency).round() as usize).max(2);
+        let step = 360. / n_samples as f64;
+        let mut lap = SimulatedLap {
+            bytes: Vec::new(),
+            scan: Scan::new(),
+        };
+        if SampleFormat::of(self.model).has_intensity() {
+            // The samples have an intensity of 0 and no interference
+            lap.scan.intensities = Some(Vec::new());
+            lap.scan.interference_flags = Some(Vec::new());
+        }
+        let mut packet_start = 0;
+        while packet_start < n_samples {
+            let packet_end = if packet_start == 0 {
+                1
+            } else {
+                (packet_start + SAMPLES_PER_PACKET).min(n_samples)
+            };
+            let start_angle = packet_start as f64 * step;
+            let end_angle = (packet_end - 1) as f64 * step;
+            let distances = self.packet_distances(
+                decoded_angle(start_angle),
+                decoded_angle(end_angle),
+                packet_end - packet_start,
+                &mut lap.scan,
+            );
+            lap.bytes.extend(self.encoder.scan_packet(
+                packet_start == 0,
+                start_angle,
+                end_angle,
+                &distances,
+            ));
+            lap.scan.packets_accepted += 1;
+            packet_start = packet_end;
+        }
+        self.n_laps += 1;
+        lap.scan.sequence = self.n_laps;
+        lap.scan.scan_frequency = lap_scan_frequency(&lap.bytes);
+        lap
+    }
+
+    /// Measures the samples of a packet and appends the points the driver keeps to `scan`.
+    /// The angles are the ones decoded from the packet, before the X2 correction.
+    fn packet_distances(
+        &mut self,
+        start_angle: f64,
+        end_angle: f64,
+        n: usize,
+        scan: &mut Scan,
+    ) -> Vec<u16> {
+        (0..n)
+            .map(|idx| {
+                let angle = sample_angle(start_angle, end_angle, n, idx);
+                let distance = self.measure(angle);
+                if distance > 0 {
+                    scan.distances.push(distance);
+                    if let Some(intensities) = &mut scan.intensities {
+                        intensities.push(0);
+                    }
+                    if let Some(flags) = &mut scan.interference_flags {
+                        flags.push(InterferenceFlag::Nothing);
+                    }
+                    scan.angles_radian
+                        .push(degree_to_radian(correct_angle(self.model, angle, distance)));
+                }
+                distance
+            })
+            .collect()
+    }
+
+    /// Distance sent for a sample decoded at `angle` degrees, 0 if nothing is hit.
+    fn measure(&mut self, angle: f64) -> u16 {
+        // The correction of a sample depends on its distance, which depends on the
+        // corrected angle: a few iterations settle both. Next to the edge of an
+        // obstacle they may not settle, and the sample is dropped.
+        let mut distance = self.quantized_range(angle);
+        let mut settled = false;
+        for _ in 0..8 {
+            let next = self.quantized_range(correct_angle(self.model, angle, distance));
+            settled = next.abs_diff(distance) <= 1;
+            distance = next;
+            if settled {
+                break;
+            }
+        }
+        if !settled {
+            return 0;
+        }
+        if distance == 0 || self.noise == 0. {
+            return distance;
+        }
+        let noisy = distance as f64 + self.noise * self.rng.gaussian();
+        noisy
+            .round()
+            .clamp(1., model_max_distance(self.model) as f64) as u16
+    }
+
+    fn quantized_range(&self, angle: f64) -> u16 {
+        self.range(degree_to_radian(angle))
+            .map_or(0, |distance| distance.round() as u16)
+    }
+}
+
+const SAMPLES_PER_PACKET: usize = 40;
+
+/// Angle the driver decodes from a packet angle in degrees, after the quantization of
+/// the packet.
+fn decoded_angle(angle: f64) -> f64 {
+    let [lo, hi] = encode_angle(angle);
+    -to_angle(lo, hi)
+}
+
+/// Xorshift64* pseudo random generator, enough for sensor noise.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // The state must not be 0
+        XorShift(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in (0, 1].
+    fn uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, with the Box-Muller transform.
+    fn gaussian(&mut self) -> f64 {
+        let radius = (-2. * self.uniform().ln()).sqrt();
+        radius * (2. * std::f64::consts::PI * self.uniform()).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::ScanDecoder;
+    use crate::event::DriverEvent;
+    use crate::run_driver;
+    use crate::test_util::{KeepInput, SCAN_RESPONSE};
+    use crate::transport::MemoryTransport;
+    use std::io::Write;
+
+    fn room() -> Vec<Shape> {
+        let mut shapes = Shape::polygon(&[
+            [-3000., -2000.],
+            [3000., -2000.],
+            [3000., 2000.],
+            [-3000., 2000.],
+        ]);
+        shapes.push(Shape::Circle {
+            center: [1000., 500.],
+            radius: 200.,
+        });
+        shapes
+    }
+
+    #[test]
+    fn test_range() {
+        let simulator = Simulator::new(YdlidarModel::X2, room()).pose(Pose {
+            x: 0.,
+            y: 500.,
+            heading: std::f64::consts::FRAC_PI_2,
+        });
+        assert!((simulator.range(0.).unwrap() - 1500.).abs() < 1e-9);
+        assert!((simulator.range(-std::f64::consts::FRAC_PI_2).unwrap() - 800.).abs() < 1e-9);
+        assert!((simulator.range(std::f64::consts::PI).unwrap() - 2500.).abs() < 1e-9);
+
+        let open_space = Simulator::new(YdlidarModel::X2, Vec::new());
+        assert_eq!(open_space.range(0.), None);
+    }
+
+    #[test]
+    fn test_invalid_scan_frequency() {
+        for frequency in [0., -7., f64::NAN] {
+            assert!(matches!(
+                Simulator::new(YdlidarModel::X2, room()).scan_frequency(frequency),
+                Err(YDLidarError::InvalidConfiguration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decode_laps() {
+        for model in [YdlidarModel::X2, YdlidarModel::TMiniPro] {
+            let mut simulator = Simulator::new(model, room())
+                .pose(Pose {
+                    x: -500.,
+                    y: 300.,
+                    heading: 0.4,
+                })
+                .scan_frequency(10.)
+                .unwrap()
+                .noise(5.);
+            let mut decoder = ScanDecoder::new(model);
+            let mut lap = simulator.lap();
+            decoder.push(&lap.bytes);
+            assert!(decoder.next_scan().unwrap().distances.is_empty());
+
+            // A lap is decoded once the next one starts
+            for _ in 0..3 {
+                let next_lap = simulator.lap();
+                decoder.push(&next_lap.bytes);
+                assert_eq!(decoder.next_scan().unwrap(), lap.scan);
+                assert_eq!(decoder.next_scan(), None);
+                lap = next_lap;
+            }
+            let n_samples = model_sample_rate(model) as usize / 10;
+            assert_eq!(simulator.lap().scan.distances.len(), n_samples);
+        }
+    }
+
+    #[test]
+    fn test_ground_truth() {
+        let simulator = Simulator::new(YdlidarModel::X2, room());
+        let mut simulator = simulator.pose(Pose {
+            x: 200.,
+            y: -100.,
+            heading: -1.,
+        });
+        let lap = simulator.lap();
+        for (&angle, &distance) in lap.scan.angles_radian.iter().zip(&lap.scan.distances) {
+            let expected = simulator.range(angle).unwrap();
+            assert!(
+                (expected - distance as f64).abs() <= 1.,
+                "{expected} {distance}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_driver() {
+        let (mut device, host) = MemoryTransport::pair();
+        device.write_all(&SCAN_RESPONSE).unwrap();
+        let mut simulator = Simulator::new(YdlidarModel::X2, room());
+        let port = KeepInput::open(host, YdlidarModel::X2);
+        let (thread, event_rx) = run_driver(port, YdlidarModel::X2, 200, 10, 0, 10).unwrap();
+
+        let lap = simulator.lap();
+        device.write_all(&lap.bytes).unwrap();
+        device.write_all(&simulator.lap().bytes).unwrap();
+        let scans: Vec<Scan> = event_rx
+            .iter()
+            .filter_map(|event| match event {
+                DriverEvent::Scan(scan) => Some(scan),
+                _ => None,
+            })
+            .take(2)
+            .collect();
+        // The driver stamps the laps with their reception times
+        let mut scan = scans[1].clone();
+        assert!(scan.first_packet_instant.is_some());
+        assert!(scan.first_packet_instant <= scan.last_packet_instant);
+        assert!(scan.first_packet_time <= scan.last_packet_time);
+        scan.first_packet_instant = None;
+        scan.last_packet_instant = None;
+        scan.first_packet_time = None;
+        scan.last_packet_time = None;
+        assert_eq!(scan, lap.scan);
+
+        drop(thread);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::constants::{CONNECT_TIMEOUT, READ_CHUNK_SIZE};
+use crate::error::YDLidarError;
+use crate::transport::Transport;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Transport to a lidar exposed on a TCP port, e.g. by a ser2net-style serial to
+/// Ethernet converter forwarding the raw bytes.
+///
+/// `run_driver` and `DriverBuilder` connect to such endpoints when given a port name
+/// of the form `tcp://host:port`.
+pub struct TcpTransport {
+    address: String,
+    stream: TcpStream,
+    timeout: Duration,
+    /// Bytes received while counting the available bytes, served before the stream.
+    pending: VecDeque<u8>,
+}
+
+impl TcpTransport {
+    /// Connects to the endpoint, waiting at most 1 second.
+    /// # Arguments
+    ///
+    /// * `address` - Endpoint such as `192.168.1.10:4001`.
+    pub fn connect(address: &str) -> Result<Self, YDLidarError> {
+        let timeout = Duration::from_millis(10);
+        let stream = connect(address, timeout)?;
+        Ok(TcpTransport {
+            address: address.to_string(),
+            stream,
+            timeout,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+/// Connects to `address`, trying each resolved address in turn.
+pub(crate) fn connect(address: &str, read_timeout: Duration) -> Result<TcpStream, YDLidarError> {
+    let connection_error = |e| YDLidarError::ConnectionFailed(address.to_string(), e);
+    let mut last_error = io::Error::from(io::ErrorKind::NotFound);
+    for addr in address.to_socket_addrs().map_err(connection_error)? {
+        match TcpStream::connect_timeout(&addr, Duration::from_millis(CONNECT_TIMEOUT)) {
+            Ok(stream) => {
+                stream.set_nodelay(true)?;
+                stream.set_read_timeout(Some(non_zero(read_timeout)))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(connection_error(last_error))
+}
+
+/// A zero read timeout is rejected by the socket, so it is rounded up.
+pub(crate) fn non_zero(timeout: Duration) -> Duration {
+    timeout.max(Duration::from_millis(1))
+}
+
+impl Transport for TcpTransport {
+    fn name(&self) -> String {
+        format!("tcp://{}", self.address)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.pending.is_empty() {
+            let n_read = buf.len().min(self.pending.len());
+            for (dst, byte) in buf.iter_mut().zip(self.pending.drain(..n_read)) {
+                *dst = byte;
+            }
+            return Ok(n_read);
+        }
+        match self.stream.read(buf) {
+            // Sockets report an elapsed read timeout as WouldBlock on unix
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::ErrorKind::TimedOut.into()),
+            result => result,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.stream.write(data)
+    }
+
+    /// Sockets cannot tell how many bytes they hold, so the received bytes are moved
+    /// to an internal buffer without blocking.
+    fn bytes_available(&mut self) -> Result<usize, YDLidarError> {
+        self.stream.set_nonblocking(true)?;
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        let result = loop {
+            match self.stream.read(&mut chunk) {
+                // The peer closed the connection, which the next read reports
+                Ok(0) => break Ok(()),
+                Ok(n) => self.pending.extend(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+        Ok(self.pending.len())
+    }
+
+    fn flush(&mut self) -> Result<(), YDLidarError> {
+        self.bytes_available()?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), YDLidarError> {
+        self.stream.set_read_timeout(Some(non_zero(timeout)))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    /// Connects to the endpoint again. Bytes received before the disconnection are
+    /// dropped.
+    fn reconnect(&mut self) -> Result<(), YDLidarError> {
+        self.stream = connect(&self.address, self.timeout)?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DriverBuilder;
+    use crate::event::DriverEvent;
+    use crate::reconnect::ReconnectPolicy;
+    use crate::test_util::{KeepInput, LAP_START};
+    use crate::{check_device_health, get_device_info};
+    use std::net::TcpListener;
+    use ydlidar_data::YdlidarModel;
+
+    #[test]
+    fn test_device_info_and_health() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0u8; 2];
+            stream.read_exact(&mut command).unwrap();
+            assert_eq!(command, [0xA5, 0x92]);
+            stream
+                .write_all(&[0xA5, 0x5A, 0x03, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00])
+                .unwrap();
+            stream.read_exact(&mut command).unwrap();
+            assert_eq!(command, [0xA5, 0x90]);
+            stream
+                .write_all(&[
+                    0xA5, 0x5A, 0x14, 0x00, 0x00, 0x00, 0x04, 0x96, 0x00, 0x01, 0x02, 0x02, 0x00,
+                    0x02, 0x02, 0x01, 0x01, 0x00, 0x03, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+                    0x01,
+                ])
+                .unwrap();
+        });
+
+        let mut transport = TcpTransport::connect(&address).unwrap();
+        assert_eq!(transport.name(), format!("tcp://{address}"));
+        check_device_health(&mut transport).unwrap();
+        let info = get_device_info(&mut transport).unwrap();
+        assert_eq!(info.model_number, 150);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_connection_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        assert!(matches!(
+            TcpTransport::connect(&address),
+            Err(YDLidarError::ConnectionFailed(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            // Replays the start of a capture, then drops the connection
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&LAP_START).unwrap();
+            drop(stream);
+
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&LAP_START).unwrap();
+            // Keeps the connection open until the client is done
+            let _ = stream.read(&mut [0u8; 1]);
+        });
+
+        let policy = ReconnectPolicy {
+            max_attempts: Some(10),
+            initial_backoff: 10,
+            max_backoff: 10,
+        };
+        let name = format!("tcp://{address}");
+        let (thread, event_rx) = DriverBuilder::new(&name, YdlidarModel::TMiniPro)
+            .read_timeout(10)
+            .reconnect(policy)
+            .build_with_transport(Box::new(KeepInput::open(&name, YdlidarModel::TMiniPro)))
+            .unwrap();
+
+        assert!(matches!(event_rx.recv().unwrap(), DriverEvent::Scan(_)));
+        assert!(matches!(event_rx.recv().unwrap(), DriverEvent::Error(_)));
+        assert!(matches!(
+            event_rx.recv().unwrap(),
+            DriverEvent::Reconnecting(1)
+        ));
+        assert!(matches!(event_rx.recv().unwrap(), DriverEvent::Reconnected));
+        assert!(matches!(event_rx.recv().unwrap(), DriverEvent::Scan(_)));
+
+        drop(thread);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! Helpers shared by the tests of the driver.
+
+use crate::driver_threads::DriverThreads;
+use crate::error::YDLidarError;
+use crate::event::DriverEvent;
+use crate::output::EventReceiver;
+use crate::run_driver;
+use crate::time::sleep_ms;
+use crate::transport::{IntoTransport, Transport};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use ydlidar_data::{model_baud_rate, Scan, YdlidarModel};
+
+/// T-mini Pro lap start packet holding a single point.
+pub(crate) const LAP_START: [u8; 13] = [
+    0xAA, 0x55, 0xC7, 0x01, 0x01, 0x15, 0x01, 0x15, 0x1B, 0x56, 0x14, 0x62, 0x02,
+];
+
+/// Response header of the device to the scan command.
+pub(crate) const SCAN_RESPONSE: [u8; 7] = [0xA5, 0x5A, 0x05, 0x00, 0x00, 0x40, 0x81];
+
+/// Answers the scan command on the device side, before the driver starts.
+pub(crate) fn answer_scan(device: &mut impl Write) {
+    device.write_all(&SCAN_RESPONSE).unwrap();
+    sleep_ms(10);
+}
+
+/// Answers the scan command on the device side and launches the driver on `port`
+/// for a T-mini Pro.
+pub(crate) fn start_driver(
+    port: impl IntoTransport,
+    device: &mut impl Write,
+    read_timeout: u64,
+) -> (DriverThreads, EventReceiver) {
+    answer_scan(device);
+    let port = KeepInput::open(port, YdlidarModel::TMiniPro);
+    run_driver(port, YdlidarModel::TMiniPro, 200, 10, 0, read_timeout).unwrap()
+}
+
+pub(crate) fn recv_scan(event_rx: &EventReceiver) -> Scan {
+    match event_rx.recv().unwrap() {
+        DriverEvent::Scan(scan) => scan,
+        event => panic!("Expected a scan but received {:?}", event),
+    }
+}
+
+/// Transport that keeps the pending data when the driver flushes it, so that a test can
+/// write to the device before the driver starts. Counts the flushes instead.
+pub(crate) struct KeepInput {
+    inner: Box<dyn Transport>,
+    flushes: Arc<AtomicUsize>,
+}
+
+impl KeepInput {
+    /// Opens `port` at the baud rate of the model.
+    pub(crate) fn open(port: impl IntoTransport, model: YdlidarModel) -> Self {
+        KeepInput {
+            inner: port.into_transport(model_baud_rate(model)).unwrap(),
+            flushes: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Number of flushes requested so far, shared with the driver threads.
+    pub(crate) fn flushes(&self) -> Arc<AtomicUsize> {
+        self.flushes.clone()
+    }
+}
+
+impl Transport for KeepInput {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.inner.write(data)
+    }
+
+    fn bytes_available(&mut self) -> Result<usize, YDLidarError> {
+        self.inner.bytes_available()
+    }
+
+    fn flush(&mut self) -> Result<(), YDLidarError> {
+        self.flushes.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), YDLidarError> {
+        self.inner.set_timeout(timeout)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), YDLidarError> {
+        self.inner.set_baud_rate(baud_rate)
+    }
+
+    fn reconnect(&mut self) -> Result<(), YDLidarError> {
+        self.inner.reconnect()
+    }
+}
@@ -0,0 +1,355 @@
+use crate::error::YDLidarError;
+use crate::rfc2217::Rfc2217Transport;
+use crate::serial::open_port;
+use crate::tcp::TcpTransport;
+use serialport::SerialPort;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Byte stream connecting the driver to a lidar.
+///
+/// Implemented for serial ports (`SerialTransport`, or a bare `Box<dyn SerialPort>` which
+/// cannot reconnect), TCP endpoints (`TcpTransport`), RFC 2217 device servers
+/// (`Rfc2217Transport`), capture files (`ReplayTransport`) and `MemoryTransport`.
+/// The reader thread owns the transport, hence the `Send` bound.
+pub trait Transport: Send {
+    /// Human readable name of the transport, such as the serial port name.
+    fn name(&self) -> String;
+
+    /// Reads the available bytes into `buf`.
+    /// Blocks until at least one byte arrives or the timeout elapses, in which case an
+    /// error of kind `TimedOut` is returned. `Ok(0)` means that the device is gone.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Writes bytes to the device and returns how many were written.
+    fn write(&mut self, data: &[u8]) -> io::Result<usize>;
+
+    /// Number of bytes received but not read yet.
+    fn bytes_available(&mut self) -> Result<usize, YDLidarError>;
+
+    /// Discards the bytes received but not read yet.
+    fn flush(&mut self) -> Result<(), YDLidarError> {
+        let n_read = self.bytes_available().unwrap_or(0);
+        let mut buf = vec![0; n_read];
+        let mut filled = 0;
+        while filled < n_read {
+            match self.read(&mut buf[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets how long `read` blocks while the device sends nothing.
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), YDLidarError>;
+
+    /// Sets the baud rate of the link. Ignored by transports without one.
+    fn set_baud_rate(&mut self, _baud_rate: u32) -> Result<(), YDLidarError> {
+        Ok(())
+    }
+
+    /// Opens the link again after the device disappeared.
+    /// Transports that cannot be reopened return an error of kind `Unsupported`.
+    fn reconnect(&mut self) -> Result<(), YDLidarError> {
+        Err(io::Error::from(io::ErrorKind::Unsupported).into())
+    }
+}
+
+impl Transport for Box<dyn SerialPort> {
+    fn name(&self) -> String {
+        SerialPort::name(self.as_ref()).unwrap_or_default()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(self, buf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        io::Write::write(self, data)
+    }
+
+    fn bytes_available(&mut self) -> Result<usize, YDLidarError> {
+        let n_u32: u32 = self.bytes_to_read()?;
+        Ok(n_u32.try_into().unwrap_or(0))
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), YDLidarError> {
+        SerialPort::set_timeout(self.as_mut(), timeout)?;
+        Ok(())
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), YDLidarError> {
+        SerialPort::set_baud_rate(self.as_mut(), baud_rate)?;
+        Ok(())
+    }
+}
+
+/// Serial port that remembers how it was opened, so that it can be reopened by name
+/// after the device disappeared.
+pub struct SerialTransport {
+    port_name: String,
+    baud_rate: u32,
+    timeout: Duration,
+    port: Box<dyn SerialPort>,
+}
+
+impl SerialTransport {
+    /// Opens the serial port.
+    /// # Arguments
+    ///
+    /// * `port_name` - Serial port name such as `/dev/ttyUSB0`.
+    /// * `baud_rate` - Baud rate, see `model_baud_rate`.
+    pub fn open(port_name: &str, baud_rate: u32) -> Result<Self, YDLidarError> {
+        let port = open_port(port_name, baud_rate)?;
+        Ok(SerialTransport {
+            port_name: port_name.to_string(),
+            baud_rate,
+            timeout: port.timeout(),
+            port,
+        })
+    }
+}
+
+impl Transport for SerialTransport {
+    fn name(&self) -> String {
+        self.port_name.clone()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Transport::read(&mut self.port, buf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        Transport::write(&mut self.port, data)
+    }
+
+    fn bytes_available(&mut self) -> Result<usize, YDLidarError> {
+        self.port.bytes_available()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), YDLidarError> {
+        Transport::set_timeout(&mut self.port, timeout)?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), YDLidarError> {
+        Transport::set_baud_rate(&mut self.port, baud_rate)?;
+        self.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    /// Reopens the port by name, keeping its baud rate and timeout.
+    fn reconnect(&mut self) -> Result<(), YDLidarError> {
+        let mut port = open_port(&self.port_name, self.baud_rate)?;
+        port.set_timeout(self.timeout)?;
+        self.port = port;
+        Ok(())
+    }
+}
+
+/// Conversion into a boxed transport, so that `run_driver` accepts either a serial
+/// port name or any transport.
+pub trait IntoTransport {
+    /// Opens the transport. `baud_rate` only applies to serial ports opened by name.
+    fn into_transport(self, baud_rate: u32) -> Result<Box<dyn Transport>, YDLidarError>;
+}
+
+/// Names of the form `tcp://host:port` connect to a `TcpTransport`, names of the form
+/// `rfc2217://host:port` to a `Rfc2217Transport`, and other names open a serial port.
+impl IntoTransport for &str {
+    fn into_transport(self, baud_rate: u32) -> Result<Box<dyn Transport>, YDLidarError> {
+        if let Some(address) = self.strip_prefix("tcp://") {
+            return Ok(Box::new(TcpTransport::connect(address)?));
+        }
+        if let Some(address) = self.strip_prefix("rfc2217://") {
+            return Ok(Box::new(Rfc2217Transport::connect(address, baud_rate)?));
+        }
+        Ok(Box::new(SerialTransport::open(self, baud_rate)?))
+    }
+}
+
+impl IntoTransport for &String {
+    fn into_transport(self, baud_rate: u32) -> Result<Box<dyn Transport>, YDLidarError> {
+        self.as_str().into_transport(baud_rate)
+    }
+}
+
+impl IntoTransport for String {
+    fn into_transport(self, baud_rate: u32) -> Result<Box<dyn Transport>, YDLidarError> {
+        self.as_str().into_transport(baud_rate)
+    }
+}
+
+impl<T: Transport + 'static> IntoTransport for T {
+    fn into_transport(self, _baud_rate: u32) -> Result<Box<dyn Transport>, YDLidarError> {
+        Ok(Box::new(self))
+    }
+}
+
+/// Bytes flowing in one direction of a `MemoryTransport` pair.
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Pipe {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// In-memory transport. Bytes written to one end of a pair are read from the other,
+/// e.g. to feed the driver with recorded or generated data.
+///
+/// Dropping one end disconnects the other: its reads return `Ok(0)` once the pending
+/// bytes are consumed and its writes fail.
+///
+/// ```
+/// use ydlidar_driver::{MemoryTransport, Transport};
+///
+/// let (mut device, mut host) = MemoryTransport::pair();
+/// device.write(&[0xAA, 0x55]).unwrap();
+/// let mut buf = [0u8; 2];
+/// assert_eq!(host.read(&mut buf).unwrap(), 2);
+/// ```
+pub struct MemoryTransport {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    timeout: Duration,
+}
+
+impl MemoryTransport {
+    /// Creates two connected transports.
+    pub fn pair() -> (MemoryTransport, MemoryTransport) {
+        let a_to_b = Arc::new(Pipe::default());
+        let b_to_a = Arc::new(Pipe::default());
+        let timeout = Duration::from_millis(10);
+        let a = MemoryTransport {
+            incoming: b_to_a.clone(),
+            outgoing: a_to_b.clone(),
+            timeout,
+        };
+        let b = MemoryTransport {
+            incoming: a_to_b,
+            outgoing: b_to_a,
+            timeout,
+        };
+        (a, b)
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let state = self.incoming.state.lock().unwrap();
+        let (mut state, _) = self
+            .incoming
+            .ready
+            .wait_timeout_while(state, self.timeout, |state| {
+                state.bytes.is_empty() && !state.closed
+            })
+            .unwrap();
+        if state.bytes.is_empty() {
+            if state.closed {
+                return Ok(0);
+            }
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let n_read = buf.len().min(state.bytes.len());
+        for (dst, byte) in buf.iter_mut().zip(state.bytes.drain(..n_read)) {
+            *dst = byte;
+        }
+        Ok(n_read)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        state.bytes.extend(data);
+        self.outgoing.ready.notify_all();
+        Ok(data.len())
+    }
+
+    fn bytes_available(&mut self) -> Result<usize, YDLidarError> {
+        Ok(self.incoming.state.lock().unwrap().bytes.len())
+    }
+
+    fn flush(&mut self) -> Result<(), YDLidarError> {
+        self.incoming.state.lock().unwrap().bytes.clear();
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), YDLidarError> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+impl io::Read for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Transport::read(self, buf)
+    }
+}
+
+impl io::Write for MemoryTransport {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        Transport::write(self, data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_pair() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        assert_eq!(Transport::write(&mut a, &[1, 2, 3]).unwrap(), 3);
+        assert_eq!(b.bytes_available().unwrap(), 3);
+
+        let mut buf = [0u8; 2];
+        assert_eq!(Transport::read(&mut b, &mut buf).unwrap(), 2);
+        assert_eq!(buf, [1, 2]);
+        Transport::flush(&mut b).unwrap();
+        assert_eq!(b.bytes_available().unwrap(), 0);
+
+        let err = Transport::read(&mut b, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(b.reconnect().is_err());
+
+        Transport::write(&mut b, &[4]).unwrap();
+        drop(b);
+        // Pending bytes are still delivered after the other end is gone
+        assert_eq!(Transport::read(&mut a, &mut buf).unwrap(), 1);
+        assert_eq!(Transport::read(&mut a, &mut buf).unwrap(), 0);
+        assert!(Transport::write(&mut a, &[5]).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant};
+
+/// Watchdog that reports a lidar which stopped streaming.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Watchdog {
+    /// Reports a stall when no valid packet is decoded for this many milliseconds.
+    pub packet_timeout: Option<u64>,
+    /// Reports a stall when no complete lap is decoded for this many milliseconds.
+    pub lap_timeout: Option<u64>,
+    /// Restarts the scan sequence each time a stall is reported.
+    pub restart_scan: bool,
+}
+
+/// Condition reported by the watchdog.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stall {
+    /// No valid packet was decoded for the given number of milliseconds.
+    NoPacket(u64),
+    /// No complete lap was decoded for the given number of milliseconds.
+    NoLap(u64),
+}
+
+/// Keeps track of the last packet and lap seen by the parser thread.
+pub(crate) struct WatchdogTimer {
+    watchdog: Watchdog,
+    last_packet: Instant,
+    last_lap: Instant,
+}
+
+impl WatchdogTimer {
+    pub(crate) fn new(watchdog: Watchdog) -> Self {
+        let now = Instant::now();
+        WatchdogTimer {
+            watchdog,
+            last_packet: now,
+            last_lap: now,
+        }
+    }
+
+    pub(crate) fn restart_scan(&self) -> bool {
+        self.watchdog.restart_scan
+    }
+
+    pub(crate) fn packet_received(&mut self) {
+        self.last_packet = Instant::now();
+    }
+
+    pub(crate) fn lap_received(&mut self) {
+        self.last_lap = Instant::now();
+    }
+
+    /// Time left until the next stall could be reported.
+    pub(crate) fn time_to_next_check(&self) -> Duration {
+        let now = Instant::now();
+        let remaining = |last: Instant, timeout: u64| {
+            (last + Duration::from_millis(timeout)).saturating_duration_since(now)
+        };
+        let packet = self
+            .watchdog
+            .packet_timeout
+            .map(|timeout| remaining(self.last_packet, timeout));
+        let lap = self
+            .watchdog
+            .lap_timeout
+            .map(|timeout| remaining(self.last_lap, timeout));
+        packet.into_iter().chain(lap).min().unwrap_or(Duration::MAX)
+    }
+
+    /// Returns the stall condition, if any. Once reported, a stall is reported again
+    /// only after another full timeout without data.
+    pub(crate) fn check(&mut self) -> Option<Stall> {
+        let now = Instant::now();
+        if let Some(timeout) = self.watchdog.packet_timeout {
+            if now.duration_since(self.last_packet) >= Duration::from_millis(timeout) {
+                self.last_packet = now;
+                return Some(Stall::NoPacket(timeout));
+            }
+        }
+        if let Some(timeout) = self.watchdog.lap_timeout {
+            if now.duration_since(self.last_lap) >= Duration::from_millis(timeout) {
+                self.last_lap = now;
+                return Some(Stall::NoLap(timeout));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::sleep_ms;
+
+    #[test]
+    fn test_check() {
+        let mut timer = WatchdogTimer::new(Watchdog {
+            packet_timeout: Some(20),
+            lap_timeout: Some(50),
+            restart_scan: false,
+        });
+        assert_eq!(timer.check(), None);
+
+        sleep_ms(30);
+        assert_eq!(timer.check(), Some(Stall::NoPacket(20)));
+        // Reported once per timeout
+        assert_eq!(timer.check(), None);
+
+        sleep_ms(30);
+        timer.packet_received();
+        assert_eq!(timer.check(), Some(Stall::NoLap(50)));
+
+        timer.lap_received();
+        assert_eq!(timer.check(), None);
+        assert!(timer.time_to_next_check() <= Duration::from_millis(20));
+    }
+}